@@ -0,0 +1,1724 @@
+use bevy::asset::load_internal_asset;
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey, NotShadowCaster};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::mesh::{self, MeshVertexAttribute, MeshVertexBufferLayout, PrimitiveTopology};
+use bevy::render::render_resource::{
+    AddressMode, AsBindGroup, Extent3d, FilterMode, RenderPipelineDescriptor, SamplerDescriptor,
+    ShaderRef, SpecializedMeshPipelineError, TextureDimension, TextureFormat, VertexFormat,
+};
+use bevy::render::texture::ImageSampler;
+use bevy::utils::HashSet;
+use bevy::window::PrimaryWindow;
+
+use crate::errors::CoordError;
+use crate::layers::LayerMaterial;
+use crate::map::{AntipodalPath, Coordinates, EARTH_RADIUS};
+use crate::point_cloud::{click_ray, ray_sphere_distance};
+
+const ARC_LINE_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x2d97_41c8_6ae3_0b5f);
+
+/// The other end of the segment a line vertex belongs to
+const ATTRIBUTE_OTHER: MeshVertexAttribute = MeshVertexAttribute::new(
+    "ArcLine_Other",
+    0x2d97_41c8_6ae3_0b60,
+    VertexFormat::Float32x3,
+);
+
+/// The next point along the line past a vertex's end of its segment, to join the segments
+/// meeting there
+const ATTRIBUTE_NEIGHBOR: MeshVertexAttribute = MeshVertexAttribute::new(
+    "ArcLine_Neighbor",
+    0x2d97_41c8_6ae3_0b61,
+    VertexFormat::Float32x3,
+);
+
+/// Width of the line at a vertex, as a multiple of the material's width
+const ATTRIBUTE_WIDTH: MeshVertexAttribute = MeshVertexAttribute::new(
+    "ArcLine_Width",
+    0x2d97_41c8_6ae3_0b63,
+    VertexFormat::Float32,
+);
+
+/// Side of the line a vertex is on, -1 or 1, and 0 at the start of its segment or 1 at the end
+const ATTRIBUTE_CORNER: MeshVertexAttribute = MeshVertexAttribute::new(
+    "ArcLine_Corner",
+    0x2d97_41c8_6ae3_0b62,
+    VertexFormat::Float32x2,
+);
+
+/// Pixels beyond the edge of a line within which a click still picks it
+const PICK_TOLERANCE: f32 = 3.0;
+
+/// Height in pixels of the flow pattern textures, across the line
+const FLOW_TEXTURE_HEIGHT: usize = 16;
+
+/// Component to store arc line data
+#[derive(Component, Clone)]
+pub struct ArcLine {
+    pub from: Coordinates,
+    pub to: Coordinates,
+    pub color: Color,
+    /// Color at the `to` end, blended from `color` along the arc
+    pub end_color: Option<Color>,
+    /// Fraction of the arc's length over which it fades in from each end
+    pub fade: f32,
+    pub segments: u32,
+    /// Height above the sphere surface at the arc's peak
+    pub arc_height: ArcHeight,
+    /// Shape of the arc's rise and fall between its ends
+    pub profile: ArcProfile,
+    /// Width of the line on screen in pixels
+    pub width: f32,
+    /// Width at the `to` end as a multiple of the width at the `from` end, or of the tube's
+    /// radius
+    pub taper: f32,
+    pub join: LineJoin,
+    /// Draws the arc as a lit, solid tube instead of a line
+    pub tube: Option<ArcTube>,
+    pub antipodal_path: AntipodalPath,
+    /// Fraction of the arc drawn, from its `from` end
+    pub progress: f32,
+}
+
+impl ArcLine {
+    pub fn new(from_lat: f32, from_lon: f32, to_lat: f32, to_lon: f32) -> Result<Self, CoordError> {
+        Ok(Self {
+            from: Coordinates::from_degrees(from_lat, from_lon)?,
+            to: Coordinates::from_degrees(to_lat, to_lon)?,
+            color: Color::YELLOW,
+            end_color: None,
+            fade: 0.0,
+            segments: 50,
+            arc_height: ArcHeight::Fixed(50.0), // Default height above surface
+            profile: ArcProfile::default(),
+            width: 2.0,
+            taper: 1.0,
+            join: LineJoin::default(),
+            tube: None,
+            antipodal_path: AntipodalPath::default(),
+            progress: 1.0,
+        })
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Blends the arc's color from `start` at its `from` end to `end` at its `to` end
+    pub fn with_gradient(mut self, start: Color, end: Color) -> Self {
+        self.color = start;
+        self.end_color = Some(end);
+        self
+    }
+
+    pub fn with_fade(mut self, fraction: f32) -> Self {
+        self.fade = fraction;
+        self
+    }
+
+    pub fn with_segments(mut self, segments: u32) -> Self {
+        self.segments = segments;
+        self
+    }
+
+    pub fn with_arc_height(mut self, height: f32) -> Self {
+        self.arc_height = ArcHeight::Fixed(height);
+        self
+    }
+
+    /// Raises the arc in proportion to the distance between its ends, see [`ArcHeight::Auto`]
+    pub fn with_auto_height(mut self, scale: f32, min: f32, max: f32) -> Self {
+        self.arc_height = ArcHeight::Auto { scale, min, max };
+        self
+    }
+
+    pub fn with_profile(mut self, profile: ArcProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub fn with_width(mut self, pixels: f32) -> Self {
+        self.width = pixels;
+        self
+    }
+
+    pub fn with_taper(mut self, taper: f32) -> Self {
+        self.taper = taper;
+        self
+    }
+
+    pub fn with_join(mut self, join: LineJoin) -> Self {
+        self.join = join;
+        self
+    }
+
+    /// Draws the arc as a tube of `radius` world units, with `radial_segments` faces around it
+    pub fn with_tube(mut self, radius: f32, radial_segments: u32) -> Self {
+        self.tube = Some(ArcTube {
+            radius,
+            radial_segments,
+        });
+        self
+    }
+
+    pub fn with_antipodal_path(mut self, path: AntipodalPath) -> Self {
+        self.antipodal_path = path;
+        self
+    }
+
+    pub fn with_progress(mut self, progress: f32) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Points along the drawn part of the arc
+    pub fn path(&self) -> ArcPath {
+        let peak = self.arc_height.peak(self.from.angle_to(&self.to));
+        let points =
+            self.from
+                .arc_to_with_heights(&self.to, self.segments, &self.antipodal_path, |t| {
+                    peak * self.profile.height(t)
+                });
+        let path = ArcPath::new(points);
+        path.truncated(path.length() * self.progress.clamp(0.0, 1.0))
+    }
+
+    /// Color, and width as a multiple of `width`, a fraction `t` of the way along the whole arc
+    pub fn style_at(&self, t: f32) -> (Color, f32) {
+        let t = t.clamp(0.0, 1.0);
+        let mut color = match self.end_color {
+            Some(end) => {
+                let start = Vec4::from(self.color.as_linear_rgba_f32());
+                let blend = start.lerp(Vec4::from(end.as_linear_rgba_f32()), t);
+                Color::rgba_linear(blend.x, blend.y, blend.z, blend.w)
+            }
+            None => self.color,
+        };
+        if self.fade > 0.0 {
+            let fade = (t.min(1.0 - t) / self.fade).min(1.0);
+            color.set_a(color.a() * fade);
+        }
+        (color, 1.0 + (self.taper - 1.0) * t)
+    }
+
+    /// Linear vertex color and width multiple at each point of `path`, the arc's drawn part.
+    /// Styles are spread over the whole arc, so they stay put as its `progress` grows.
+    fn point_styles(&self, path: &ArcPath) -> Vec<([f32; 4], f32)> {
+        let length = path.length() / self.progress.clamp(f32::EPSILON, 1.0);
+        path.distances
+            .iter()
+            .map(|distance| {
+                let t = if length > 0.0 { distance / length } else { 0.0 };
+                let (color, width) = self.style_at(t);
+                (color.as_linear_rgba_f32(), width)
+            })
+            .collect()
+    }
+
+    /// Whether any part of the arc is see-through
+    fn is_translucent(&self) -> bool {
+        self.color.a() < 1.0
+            || self.fade > 0.0
+            || matches!(self.end_color, Some(end) if end.a() < 1.0)
+    }
+}
+
+/// How high an arc rises above the surface at its peak
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArcHeight {
+    /// This many world units, however far apart the ends are
+    Fixed(f32),
+    /// `scale` world units per radian of great-circle distance between the ends, clamped
+    /// between `min` and `max`, so short hops stay low and long-haul routes rise higher
+    Auto { scale: f32, min: f32, max: f32 },
+}
+
+impl ArcHeight {
+    /// Height of the peak of an arc between ends `angle` radians apart
+    pub fn peak(&self, angle: f32) -> f32 {
+        match *self {
+            ArcHeight::Fixed(height) => height,
+            ArcHeight::Auto { scale, min, max } => (angle * scale).clamp(min, max.max(min)),
+        }
+    }
+}
+
+/// Shape of an arc's height along its length, as a fraction of the peak height at each
+/// fraction of the way from its `from` end
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ArcProfile {
+    /// Rises and falls steadily, peaking in the middle
+    #[default]
+    Parabolic,
+    /// Like `Parabolic`, but rises more steeply and stays near the peak for longer
+    Sine,
+    /// A cubic Bézier curve from the surface at each end, with the inner control points at
+    /// these multiples of the peak height. `CubicBezier(4.0 / 3.0, 4.0 / 3.0)` is the parabola,
+    /// and different heights push the peak towards the higher one.
+    CubicBezier(f32, f32),
+    /// Climbs over the first `ramp` fraction of the arc, cruises at the peak height, and
+    /// descends over the last `ramp`, like a flight
+    Cruise { ramp: f32 },
+}
+
+impl ArcProfile {
+    /// Fraction of the peak height `t` of the way along the arc
+    pub fn height(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match *self {
+            ArcProfile::Parabolic => 4.0 * t * (1.0 - t),
+            ArcProfile::Sine => (t * std::f32::consts::PI).sin(),
+            ArcProfile::CubicBezier(first, second) => {
+                3.0 * (1.0 - t) * t * ((1.0 - t) * first + t * second)
+            }
+            ArcProfile::Cruise { ramp } => {
+                let ramp = ramp.clamp(f32::EPSILON, 0.5);
+                let smoothstep = |x: f32| {
+                    let x = (x / ramp).clamp(0.0, 1.0);
+                    x * x * (3.0 - 2.0 * x)
+                };
+                smoothstep(t) * smoothstep(1.0 - t)
+            }
+        }
+    }
+}
+
+/// Cross-section of an arc drawn as a tube, which looks solid from any angle and is lit by the
+/// scene's lights. Tubes are drawn with a `StandardMaterial`, so flows and a line's `width` and
+/// `join` don't apply to them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArcTube {
+    /// Radius in world units
+    pub radius: f32,
+    /// Vertices around each ring of the tube
+    pub radial_segments: u32,
+}
+
+/// Points along an arc line's built mesh, with the distance along the line to each in world
+/// units
+#[derive(Component, Clone, Debug, Default)]
+pub struct ArcPath {
+    pub points: Vec<Vec3>,
+    pub distances: Vec<f32>,
+}
+
+impl ArcPath {
+    pub fn new(points: Vec<Vec3>) -> Self {
+        let mut distance = 0.0;
+        let distances = std::iter::once(0.0)
+            .chain(points.windows(2).map(|pair| {
+                distance += pair[0].distance(pair[1]);
+                distance
+            }))
+            .take(points.len())
+            .collect();
+        Self { points, distances }
+    }
+
+    /// Total length of the line
+    pub fn length(&self) -> f32 {
+        self.distances.last().copied().unwrap_or(0.0)
+    }
+
+    /// The first `length` of the line, ending partway along a segment if need be
+    pub fn truncated(&self, length: f32) -> ArcPath {
+        let Some(end) = self
+            .distances
+            .iter()
+            .position(|distance| *distance >= length)
+        else {
+            return self.clone();
+        };
+        let mut points = self.points[..end].to_vec();
+        if end == 0 {
+            points.push(self.points[0]);
+        } else {
+            let (start, segment) = (
+                self.distances[end - 1],
+                self.distances[end] - self.distances[end - 1],
+            );
+            let t = if segment > 0.0 {
+                (length - start) / segment
+            } else {
+                1.0
+            };
+            points.push(self.points[end - 1].lerp(self.points[end], t));
+        }
+        ArcPath::new(points)
+    }
+}
+
+/// Easing curves for animations, mapping linear progress from 0 to 1 onto eased progress
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    /// Starts slowly
+    EaseIn,
+    /// Ends slowly
+    EaseOut,
+    /// Starts and ends slowly
+    #[default]
+    EaseInOut,
+}
+
+impl Easing {
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+/// Draws an [`ArcLine`] on the same entity from its origin to its destination over time, by
+/// animating its `progress`.
+///
+/// The arc starts growing after `delay` seconds, so staggered delays reveal a sequence of
+/// routes one after another. An [`ArcDrawn`] event is sent once it is complete, and this
+/// component is removed.
+#[derive(Component, Clone, Debug)]
+pub struct ArcReveal {
+    /// Seconds the arc takes to grow
+    pub duration: f32,
+    /// Seconds before the arc starts growing
+    pub delay: f32,
+    pub easing: Easing,
+    /// Seconds since the reveal started, including the delay
+    pub elapsed: f32,
+}
+
+impl ArcReveal {
+    pub fn new(duration: f32) -> Self {
+        Self {
+            duration,
+            delay: 0.0,
+            easing: Easing::default(),
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn with_delay(mut self, seconds: f32) -> Self {
+        self.delay = seconds;
+        self
+    }
+
+    pub fn with_easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    /// Eased progress of the arc, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        let t = if self.duration > 0.0 {
+            (self.elapsed - self.delay) / self.duration
+        } else if self.elapsed >= self.delay {
+            1.0
+        } else {
+            0.0
+        };
+        self.easing.apply(t)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.delay + self.duration
+    }
+}
+
+/// Sent when an [`ArcReveal`] has finished drawing its arc
+pub struct ArcDrawn {
+    pub entity: Entity,
+}
+
+/// System to advance [`ArcReveal`]s and grow their arcs
+pub fn reveal_arcs(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut arcs: Query<(Entity, &mut ArcReveal, &mut ArcLine)>,
+    mut drawn: EventWriter<ArcDrawn>,
+) {
+    for (entity, mut reveal, mut arc) in arcs.iter_mut() {
+        reveal.elapsed += time.delta_seconds();
+        let progress = reveal.progress();
+        // Unchanged arcs, e.g. still waiting out their delay, aren't rebuilt
+        if arc.progress != progress {
+            arc.progress = progress;
+        }
+        if reveal.is_finished() {
+            commands.entity(entity).remove::<ArcReveal>();
+            drawn.send(ArcDrawn { entity });
+        }
+    }
+}
+
+/// Shape of the marks moving along a flowing arc
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowPattern {
+    /// Dashes as long as the gaps between them
+    Dashes,
+    /// Pulses that brighten towards their leading edge, like comets
+    Pulses,
+    /// Dots a quarter of the spacing long, rounded off across the line
+    Particles,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowDirection {
+    /// From the arc's `from` end to its `to` end
+    Forward,
+    Reverse,
+}
+
+/// Animates an [`ArcLine`] on the same entity with marks flowing along it, to show the
+/// direction and volume of traffic on a route.
+///
+/// Marks repeat every `spacing` world units and move at `speed` world units per second, so
+/// busier routes can be given denser or faster flows. The rest of the line is drawn with
+/// `trail_opacity`.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct ArcFlow {
+    pub pattern: FlowPattern,
+    pub speed: f32,
+    pub spacing: f32,
+    pub direction: FlowDirection,
+    pub trail_opacity: f32,
+}
+
+impl ArcFlow {
+    pub fn new(pattern: FlowPattern) -> Self {
+        Self {
+            pattern,
+            speed: 30.0,
+            spacing: 12.0,
+            direction: FlowDirection::Forward,
+            trail_opacity: 0.2,
+        }
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_spacing(mut self, spacing: f32) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    pub fn with_direction(mut self, direction: FlowDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn with_trail_opacity(mut self, opacity: f32) -> Self {
+        self.trail_opacity = opacity;
+        self
+    }
+
+    /// Texture coordinate along the line, in repeats of the pattern, of a point `distance`
+    /// along it after `elapsed` seconds
+    pub fn coordinate(&self, distance: f32, elapsed: f64) -> f32 {
+        distance / self.signed_spacing() - self.phase(elapsed)
+    }
+
+    /// Spacing of the marks, negated for flows running backwards
+    fn signed_spacing(&self) -> f32 {
+        let spacing = self.spacing.max(f32::EPSILON);
+        match self.direction {
+            FlowDirection::Forward => spacing,
+            FlowDirection::Reverse => -spacing,
+        }
+    }
+
+    /// Repeats of the pattern the marks have moved after `elapsed` seconds
+    fn phase(&self, elapsed: f64) -> f32 {
+        let spacing = self.spacing.max(f32::EPSILON);
+        // Wrapped so it keeps its precision however long the app runs
+        (elapsed * self.speed as f64 / spacing as f64).rem_euclid(1.0) as f32
+    }
+
+    /// Opacity of the pattern at `along`, from 0 to 1 over one repeat, and `across` the line
+    /// from 0 to 1
+    fn opacity(&self, along: f32, across: f32) -> f32 {
+        let mark = match self.pattern {
+            FlowPattern::Dashes => {
+                if along < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            FlowPattern::Pulses => along * along * along,
+            FlowPattern::Particles => {
+                let along = (along - 0.5) * 4.0;
+                let across = (across - 0.5) * 2.0;
+                let radius = (along * along + across * across).sqrt();
+                ((1.0 - radius) * 4.0).clamp(0.0, 1.0)
+            }
+        };
+        mark.max(self.trail_opacity)
+    }
+
+    /// Texture of one repeat of the pattern, to be sampled with [`ArcFlow::coordinate`]
+    pub fn image(&self) -> Image {
+        let width = 64;
+        let mut data = Vec::with_capacity(width * FLOW_TEXTURE_HEIGHT * 4);
+        for y in 0..FLOW_TEXTURE_HEIGHT {
+            let across = (y as f32 + 0.5) / FLOW_TEXTURE_HEIGHT as f32;
+            for x in 0..width {
+                let along = (x as f32 + 0.5) / width as f32;
+                let alpha = (self.opacity(along, across) * 255.0).round() as u8;
+                data.extend([255, 255, 255, alpha]);
+            }
+        }
+        let mut image = Image::new(
+            Extent3d {
+                width: width as u32,
+                height: FLOW_TEXTURE_HEIGHT as u32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+            address_mode_u: AddressMode::Repeat,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+        image
+    }
+}
+
+/// How the segments of a line are joined where they meet
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LineJoin {
+    /// Sharp corners, cut short past the material's `miter_limit`
+    #[default]
+    Miter,
+    Round,
+}
+
+/// Material of arc lines, which are drawn `width` pixels wide whatever their distance from the
+/// camera.
+///
+/// Line meshes are ribbons with no width of their own, widened in screen space by the vertex
+/// shader, with their edges anti-aliased. The material's color multiplies the meshes' vertex
+/// colors and the optional flow texture, which repeats every `flow_spacing` along the line.
+#[derive(AsBindGroup, TypeUuid, Clone, Debug)]
+#[uuid = "0c5e3f7a-94b2-4d18-8e6a-2b71d9f03c45"]
+#[bind_group_data(ArcLineMaterialKey)]
+pub struct ArcLineMaterial {
+    #[uniform(0)]
+    pub color: Color,
+    /// Width of the lines in pixels
+    #[uniform(0)]
+    pub width: f32,
+    /// Longest a miter join may reach from the line, in half widths, before it is cut short
+    #[uniform(0)]
+    pub miter_limit: f32,
+    /// Length in world units of one repeat of the flow texture along the line, negative to
+    /// run it from the line's end
+    #[uniform(0)]
+    pub flow_spacing: f32,
+    /// Offset of the flow texture along the line, in repeats, animated to move the marks
+    #[uniform(0)]
+    pub flow_phase: f32,
+    #[texture(1)]
+    #[sampler(2)]
+    pub flow_texture: Option<Handle<Image>>,
+    pub join: LineJoin,
+    pub alpha_mode: AlphaMode,
+    pub depth_bias: f32,
+}
+
+impl ArcLineMaterial {
+    pub fn new(color: Color, width: f32) -> Self {
+        Self {
+            color,
+            width,
+            miter_limit: 4.0,
+            flow_spacing: 1.0,
+            flow_phase: 0.0,
+            flow_texture: None,
+            join: LineJoin::default(),
+            alpha_mode: AlphaMode::Blend,
+            depth_bias: 0.0,
+        }
+    }
+
+    pub fn with_join(mut self, join: LineJoin) -> Self {
+        self.join = join;
+        self
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ArcLineMaterialKey {
+    join: LineJoin,
+    depth_bias: i32,
+}
+
+impl From<&ArcLineMaterial> for ArcLineMaterialKey {
+    fn from(material: &ArcLineMaterial) -> Self {
+        Self {
+            join: material.join,
+            depth_bias: material.depth_bias as i32,
+        }
+    }
+}
+
+impl Material for ArcLineMaterial {
+    fn vertex_shader() -> ShaderRef {
+        ARC_LINE_SHADER_HANDLE.typed().into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        ARC_LINE_SHADER_HANDLE.typed().into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn depth_bias(&self) -> f32 {
+        self.depth_bias
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            ATTRIBUTE_OTHER.at_shader_location(1),
+            ATTRIBUTE_NEIGHBOR.at_shader_location(2),
+            ATTRIBUTE_CORNER.at_shader_location(3),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(4),
+            Mesh::ATTRIBUTE_COLOR.at_shader_location(5),
+            ATTRIBUTE_WIDTH.at_shader_location(6),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        // Ribbons face the camera however they're turned
+        descriptor.primitive.cull_mode = None;
+        if key.bind_group_data.join == LineJoin::Round {
+            descriptor.vertex.shader_defs.push("ROUND_JOINS".into());
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push("ROUND_JOINS".into());
+            }
+        }
+        if let Some(depth_stencil) = descriptor.depth_stencil.as_mut() {
+            depth_stencil.bias.constant = key.bind_group_data.depth_bias;
+        }
+        Ok(())
+    }
+}
+
+impl LayerMaterial for ArcLineMaterial {
+    fn alpha(&self) -> f32 {
+        self.color.a()
+    }
+
+    fn set_alpha(&mut self, alpha: f32) {
+        self.color.set_a(alpha);
+    }
+
+    fn set_alpha_mode(&mut self, alpha_mode: AlphaMode) {
+        self.alpha_mode = alpha_mode;
+    }
+
+    fn set_depth_bias(&mut self, depth_bias: f32) {
+        self.depth_bias = depth_bias;
+    }
+}
+
+/// Renders [`ArcLine`]s and [`ArcLayer`]s with [`ArcLineMaterial`]. Add it after
+/// `DefaultPlugins`.
+pub struct ArcLinePlugin;
+
+impl Plugin for ArcLinePlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            ARC_LINE_SHADER_HANDLE,
+            "arc_line.wgsl",
+            Shader::from_wgsl
+        );
+        app.add_plugin(MaterialPlugin::<ArcLineMaterial> {
+            prepass_enabled: false,
+            ..default()
+        });
+    }
+}
+
+/// System to build arc line meshes as arcs are spawned or changed.
+///
+/// A changed arc rebuilds its mesh and restyles its material in place, so the same assets are
+/// reused, unless it switched between a line and a tube, which take different materials.
+/// Removing the `ArcLine` component removes the mesh and material too, which frees them once
+/// nothing else holds their handles.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_arc_line_meshes(
+    mut commands: Commands,
+    query: Query<(Entity, &ArcLine), Changed<ArcLine>>,
+    arc_meshes: Query<&Handle<Mesh>, With<ArcLine>>,
+    line_materials: Query<&Handle<ArcLineMaterial>, With<ArcLine>>,
+    tube_materials: Query<&Handle<StandardMaterial>, With<ArcLine>>,
+    mut paths: Query<&mut ArcPath>,
+    mut removed: RemovedComponents<ArcLine>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ArcLineMaterial>>,
+    mut standard_materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, arc) in query.iter() {
+        let path = arc.path();
+        let styles = arc.point_styles(&path);
+        let arc_mesh = match arc.tube {
+            Some(tube) => create_tube_mesh(&path, &styles, tube.radius, tube.radial_segments),
+            None => create_line_mesh(&path, &styles),
+        };
+
+        // Arcs with nothing to draw yet, like one about to be revealed, go without a mesh
+        match (arc_meshes.get(entity), arc_mesh) {
+            (Ok(mesh), Some(arc_mesh)) => {
+                if let Some(mesh) = meshes.get_mut(mesh) {
+                    *mesh = arc_mesh;
+                }
+            }
+            (Ok(_), None) => {
+                commands.entity(entity).remove::<Handle<Mesh>>();
+            }
+            (Err(_), Some(arc_mesh)) => {
+                commands.entity(entity).insert(meshes.add(arc_mesh));
+            }
+            (Err(_), None) => {}
+        }
+        match paths.get_mut(entity) {
+            Ok(mut old_path) => *old_path = path,
+            Err(_) => {
+                commands
+                    .entity(entity)
+                    .insert((SpatialBundle::default(), path));
+            }
+        }
+
+        // Only touch a material if its style changed, so it isn't re-uploaded. Colors are in
+        // the vertices.
+        match (
+            arc.tube,
+            line_materials.get(entity),
+            tube_materials.get(entity),
+        ) {
+            (None, Ok(material), _) => {
+                if matches!(
+                    materials.get(material),
+                    Some(material) if material.width != arc.width || material.join != arc.join
+                ) {
+                    if let Some(material) = materials.get_mut(material) {
+                        material.width = arc.width;
+                        material.join = arc.join;
+                    }
+                }
+            }
+            (None, Err(_), _) => {
+                commands
+                    .entity(entity)
+                    .remove::<Handle<StandardMaterial>>()
+                    .insert((
+                        materials
+                            .add(ArcLineMaterial::new(Color::WHITE, arc.width).with_join(arc.join)),
+                        NotShadowCaster,
+                    ));
+            }
+            (Some(_), _, Ok(material)) => {
+                if matches!(
+                    standard_materials.get(material),
+                    Some(material) if material.alpha_mode != tube_material(arc).alpha_mode
+                ) {
+                    if let Some(material) = standard_materials.get_mut(material) {
+                        *material = tube_material(arc);
+                    }
+                }
+            }
+            (Some(_), _, Err(_)) => {
+                commands
+                    .entity(entity)
+                    .remove::<(Handle<ArcLineMaterial>, NotShadowCaster)>()
+                    .insert(standard_materials.add(tube_material(arc)));
+            }
+        }
+    }
+
+    for entity in removed.iter() {
+        if let Some(mut entity) = commands.get_entity(entity) {
+            entity.remove::<(
+                Handle<Mesh>,
+                Handle<ArcLineMaterial>,
+                Handle<StandardMaterial>,
+                NotShadowCaster,
+                ArcPath,
+            )>();
+        }
+    }
+}
+
+/// Lit material of an arc drawn as a tube, colored by the vertex colors
+fn tube_material(arc: &ArcLine) -> StandardMaterial {
+    StandardMaterial {
+        alpha_mode: if arc.is_translucent() {
+            AlphaMode::Blend
+        } else {
+            AlphaMode::Opaque
+        },
+        ..default()
+    }
+}
+
+/// System to move the marks along flowing arcs, and to give arcs their flow's texture when it
+/// is added or changed, or take it away when it is removed.
+///
+/// Only the material's phase changes from frame to frame. The meshes' texture coordinates are
+/// their distances along the arcs and are never rewritten.
+pub fn animate_arc_flows(
+    time: Res<Time>,
+    flows: Query<(Entity, Ref<ArcFlow>)>,
+    arcs: Query<&Handle<ArcLineMaterial>>,
+    mut removed: RemovedComponents<ArcFlow>,
+    mut materials: ResMut<Assets<ArcLineMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let elapsed = time.elapsed_seconds_f64();
+    for (entity, flow) in flows.iter() {
+        let Some(material) = arcs
+            .get(entity)
+            .ok()
+            .and_then(|material| materials.get_mut(material))
+        else {
+            continue;
+        };
+        if flow.is_changed() || material.flow_texture.is_none() {
+            material.flow_texture = Some(images.add(flow.image()));
+        }
+        material.flow_spacing = flow.signed_spacing();
+        material.flow_phase = flow.phase(elapsed);
+    }
+
+    for entity in removed.iter() {
+        let Some(material) = arcs
+            .get(entity)
+            .ok()
+            .and_then(|material| materials.get_mut(material))
+        else {
+            continue;
+        };
+        material.flow_texture = None;
+        material.flow_spacing = 1.0;
+        material.flow_phase = 0.0;
+    }
+}
+
+/// Draws many arcs as a few merged meshes, for datasets with too many routes to give each its
+/// own entity.
+///
+/// Arcs are split into chunks of 1024, or the size given to [`ArcLayer::with_chunk_size`],
+/// each drawn by one child mesh with the arcs' colors as vertex colors and a material shared
+/// by the whole layer. All arcs of a layer have its width and join rather than their own,
+/// though gradients, fades and tapers still apply, and are drawn as lines even if they have a
+/// `tube`. Changing arcs through [`ArcLayer::push`] or [`ArcLayer::set`] only rebuilds the
+/// chunks they are in. Flows aren't drawn for arcs in a layer, but their `progress` is. Clicks
+/// on an arc send an [`ArcClicked`] event with its index.
+#[derive(Component)]
+pub struct ArcLayer {
+    arcs: Vec<ArcLine>,
+    chunk_size: usize,
+    /// Indices of arcs changed since the chunks were last built
+    changed: HashSet<usize>,
+    rebuild_all: bool,
+    chunks: Vec<Entity>,
+    /// Width of the lines in pixels
+    width: f32,
+    join: LineJoin,
+    material: Option<Handle<ArcLineMaterial>>,
+}
+
+impl ArcLayer {
+    pub fn new(arcs: impl IntoIterator<Item = ArcLine>) -> Self {
+        Self {
+            arcs: arcs.into_iter().collect(),
+            chunk_size: 1024,
+            changed: HashSet::default(),
+            rebuild_all: true,
+            chunks: Vec::new(),
+            width: 2.0,
+            join: LineJoin::default(),
+            material: None,
+        }
+    }
+
+    pub fn with_chunk_size(mut self, arcs: usize) -> Self {
+        self.chunk_size = arcs;
+        self
+    }
+
+    pub fn with_width(mut self, pixels: f32) -> Self {
+        self.width = pixels;
+        self
+    }
+
+    pub fn with_join(mut self, join: LineJoin) -> Self {
+        self.join = join;
+        self
+    }
+
+    /// Changes the width of all of the layer's lines, in pixels
+    pub fn set_width(&mut self, pixels: f32) {
+        self.width = pixels;
+    }
+
+    pub fn arcs(&self) -> &[ArcLine] {
+        &self.arcs
+    }
+
+    pub fn get(&self, index: usize) -> Option<&ArcLine> {
+        self.arcs.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.arcs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.arcs.is_empty()
+    }
+
+    /// Adds an arc, returning its index
+    pub fn push(&mut self, arc: ArcLine) -> usize {
+        self.arcs.push(arc);
+        self.changed.insert(self.arcs.len() - 1);
+        self.arcs.len() - 1
+    }
+
+    /// Replaces the arc at `index`, if there is one
+    pub fn set(&mut self, index: usize, arc: ArcLine) {
+        if let Some(old) = self.arcs.get_mut(index) {
+            *old = arc;
+            self.changed.insert(index);
+        }
+    }
+
+    /// Indices of the chunks to rebuild, clearing the record of changes
+    fn take_stale_chunks(&mut self) -> Vec<usize> {
+        let chunk_size = self.chunk_size.max(1);
+        if std::mem::take(&mut self.rebuild_all) {
+            self.changed.clear();
+            return (0..self.arcs.len().div_ceil(chunk_size)).collect();
+        }
+        let mut stale: Vec<usize> = self
+            .changed
+            .drain()
+            .map(|index| index / chunk_size)
+            .collect();
+        stale.sort_unstable();
+        stale.dedup();
+        stale
+    }
+
+    /// Range of arc indices in a chunk
+    fn chunk_range(&self, chunk: usize) -> std::ops::Range<usize> {
+        let chunk_size = self.chunk_size.max(1);
+        let start = (chunk * chunk_size).min(self.arcs.len());
+        start..(start + chunk_size).min(self.arcs.len())
+    }
+}
+
+/// One merged mesh of an [`ArcLayer`]'s arcs
+#[derive(Component)]
+pub struct ArcChunk {
+    pub layer: Entity,
+    /// Index in the layer of the chunk's first arc
+    pub first: usize,
+    /// Center and radius of a sphere around each of the chunk's arcs, for picking
+    bounds: Vec<(Vec3, f32)>,
+}
+
+/// Sent when an arc of an [`ArcLayer`] is clicked
+#[derive(Debug)]
+pub struct ArcClicked {
+    pub layer: Entity,
+    /// Index into the layer's arcs
+    pub index: usize,
+}
+
+/// Sphere around all points of a path
+fn path_bounds(path: &ArcPath) -> (Vec3, f32) {
+    let (min, max) = path.points.iter().fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(min, max), point| (min.min(*point), max.max(*point)),
+    );
+    let center = (min + max) / 2.0;
+    let radius = path
+        .points
+        .iter()
+        .map(|point| point.distance(center))
+        .fold(0.0, f32::max);
+    (center, radius)
+}
+
+/// System to build the chunk meshes of arc layers, rebuilding only the chunks whose arcs
+/// changed
+pub fn update_arc_layers(
+    mut commands: Commands,
+    mut layers: Query<(Entity, &mut ArcLayer), Changed<ArcLayer>>,
+    mut chunks: Query<(&mut ArcChunk, &Handle<Mesh>, &mut Visibility)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ArcLineMaterial>>,
+) {
+    for (entity, mut layer) in layers.iter_mut() {
+        let layer = layer.bypass_change_detection();
+        let (width, join) = (layer.width, layer.join);
+        let material = layer
+            .material
+            .get_or_insert_with(|| {
+                // Colored by the vertex colors
+                materials.add(ArcLineMaterial::new(Color::WHITE, width).with_join(join))
+            })
+            .clone();
+        if matches!(
+            materials.get(&material),
+            Some(material) if material.width != width || material.join != join
+        ) {
+            if let Some(material) = materials.get_mut(&material) {
+                material.width = width;
+                material.join = join;
+            }
+        }
+
+        for chunk in layer.take_stale_chunks() {
+            let range = layer.chunk_range(chunk);
+            let mut geometry = LineGeometry::default();
+            let mut bounds = Vec::with_capacity(range.len());
+            for arc in &layer.arcs[range.clone()] {
+                let path = arc.path();
+                bounds.push(path_bounds(&path));
+                geometry.push_line(&path, &arc.point_styles(&path));
+            }
+            // Chunks with nothing to draw, e.g. while all their arcs are yet to be revealed,
+            // keep an empty mesh but are hidden, as it can't be rendered
+            let mesh = geometry.into_mesh();
+            let visibility = if mesh.is_some() {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+            let mesh = mesh.unwrap_or_else(|| Mesh::new(PrimitiveTopology::TriangleList));
+
+            match layer.chunks.get(chunk) {
+                Some(&chunk_entity) => {
+                    if let Ok((mut arc_chunk, handle, mut chunk_visibility)) =
+                        chunks.get_mut(chunk_entity)
+                    {
+                        arc_chunk.bounds = bounds;
+                        if let Some(old) = meshes.get_mut(handle) {
+                            *old = mesh;
+                        }
+                        if *chunk_visibility != visibility {
+                            *chunk_visibility = visibility;
+                        }
+                    }
+                }
+                None => {
+                    // Arcs are only ever added at the end, so new chunks are too
+                    let chunk_entity = commands
+                        .spawn((
+                            MaterialMeshBundle {
+                                mesh: meshes.add(mesh),
+                                material: material.clone(),
+                                visibility,
+                                ..default()
+                            },
+                            NotShadowCaster,
+                            ArcChunk {
+                                layer: entity,
+                                first: range.start,
+                                bounds,
+                            },
+                        ))
+                        .id();
+                    commands.entity(entity).add_child(chunk_entity);
+                    layer.chunks.push(chunk_entity);
+                }
+            }
+        }
+    }
+}
+
+/// Distance along a ray to its closest approach to a line segment, and the distance between
+/// them there
+fn ray_segment_distance(ray: &Ray, start: Vec3, end: Vec3) -> (f32, f32) {
+    let direction = ray.direction;
+    let segment = end - start;
+    let offset = ray.origin - start;
+    let (dd, ee, de) = (
+        direction.dot(direction),
+        segment.dot(segment),
+        direction.dot(segment),
+    );
+    let (dr, er) = (direction.dot(offset), segment.dot(offset));
+    if ee <= f32::EPSILON {
+        let t = (-dr / dd).max(0.0);
+        return (t, (offset + direction * t).length());
+    }
+    // Closest points of the infinite lines, then clamped to the segment and the ray
+    let denominator = dd * ee - de * de;
+    let s = if denominator > f32::EPSILON {
+        ((dd * er - de * dr) / denominator).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let t = ((s * de - dr) / dd).max(0.0);
+    let s = ((er + t * de) / ee).clamp(0.0, 1.0);
+    (t, (offset + direction * t - segment * s).length())
+}
+
+/// Distance along a ray to where it passes within `tolerance` of a path, if it does. The
+/// tolerance is an angle in radians seen from the ray's origin, so it covers the same number of
+/// pixels however far away the path is.
+pub fn pick_path(ray: &Ray, path: &ArcPath, tolerance: f32) -> Option<f32> {
+    path.points
+        .windows(2)
+        .map(|pair| ray_segment_distance(ray, pair[0], pair[1]))
+        .filter(|(t, distance)| *distance <= t * tolerance)
+        .map(|(t, _)| t)
+        .min_by(f32::total_cmp)
+}
+
+/// Nearest arc of a layer's chunk that a ray passes within `tolerance` radians of, and the
+/// distance to it along the ray. The chunk's arcs are placed by its `transform`.
+fn pick_chunk(
+    ray: &Ray,
+    chunk: &ArcChunk,
+    layer: &ArcLayer,
+    transform: &GlobalTransform,
+    tolerance: f32,
+) -> Option<(usize, f32)> {
+    // Pick in the layer's space, renormalizing the ray's direction there. Distances along it
+    // are divided by `scale` to get back to world space.
+    let inverse = transform.compute_matrix().inverse();
+    let direction = inverse.transform_vector3(ray.direction);
+    let scale = direction.length();
+    let ray = Ray {
+        origin: inverse.transform_point3(ray.origin),
+        direction: direction / scale,
+    };
+    chunk
+        .bounds
+        .iter()
+        .enumerate()
+        // Only arcs whose bounds the ray passes through are checked closely
+        .filter(|(_, (center, radius))| {
+            let offset = *center - ray.origin;
+            let along = offset.dot(ray.direction).max(0.0);
+            offset.distance(ray.direction * along) <= radius + (along + radius) * tolerance
+        })
+        .filter_map(|(offset, _)| {
+            let index = chunk.first + offset;
+            let t = pick_path(&ray, &layer.get(index)?.path(), tolerance)?;
+            Some((index, t / scale))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// System to send [`ArcClicked`] for the nearest arc of a layer under the cursor on a click.
+///
+/// Clicks within a few pixels of a line pick it. Releases after dragging the camera are ignored,
+/// as are arcs hidden behind the globe.
+#[allow(clippy::too_many_arguments)]
+pub fn pick_arc_layers(
+    mouse: Res<Input<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    projections: Query<(&Camera, &Projection), With<Camera3d>>,
+    layers: Query<&ArcLayer>,
+    chunks: Query<(&ArcChunk, &GlobalTransform, &ComputedVisibility)>,
+    mut press_position: Local<Option<Vec2>>,
+    mut events: EventWriter<ArcClicked>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    let Some(ray) = click_ray(&mouse, window, &cameras, &mut press_position) else {
+        return;
+    };
+    let Some((camera, Projection::Perspective(projection))) =
+        projections.iter().find(|(camera, _)| camera.is_active)
+    else {
+        return;
+    };
+    let Some(viewport) = camera.physical_viewport_size() else {
+        return;
+    };
+    // Line widths are in physical pixels
+    let angle_per_pixel = projection.fov / viewport.y as f32;
+
+    let globe = ray_sphere_distance(&ray, Vec3::ZERO, EARTH_RADIUS).unwrap_or(f32::INFINITY);
+    let hit = chunks
+        .iter()
+        .filter(|(.., visibility)| visibility.is_visible())
+        .filter_map(|(chunk, transform, _)| {
+            let layer = layers.get(chunk.layer).ok()?;
+            let tolerance = (layer.width / 2.0 + PICK_TOLERANCE) * angle_per_pixel;
+            let (index, t) = pick_chunk(&ray, chunk, layer, transform, tolerance)?;
+            Some((chunk.layer, index, t))
+        })
+        .filter(|(.., t)| *t <= globe)
+        .min_by(|a, b| a.2.total_cmp(&b.2));
+    if let Some((layer, index, _)) = hit {
+        events.send(ArcClicked { layer, index });
+    }
+}
+
+/// Texture coordinates of a line mesh, with `u` the distance along the line to each point and
+/// `v` from 0 to 1 across it
+fn line_uvs(distances: &[f32]) -> Vec<[f32; 2]> {
+    let mut uvs = Vec::with_capacity(distances.len().saturating_sub(1) * 4);
+    for pair in distances.windows(2) {
+        let (start, end) = (pair[0], pair[1]);
+        uvs.extend_from_slice(&[[start, 0.0], [start, 1.0], [end, 1.0], [end, 0.0]]);
+    }
+    uvs
+}
+
+/// Vertex data of one or more lines, to be merged into a single mesh
+#[derive(Default)]
+struct LineGeometry {
+    vertices: Vec<Vec3>,
+    others: Vec<Vec3>,
+    neighbors: Vec<Vec3>,
+    corners: Vec<[f32; 2]>,
+    uvs: Vec<[f32; 2]>,
+    colors: Vec<[f32; 4]>,
+    widths: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+impl LineGeometry {
+    /// Add a line along a path, as one quad per segment with no width of its own for
+    /// [`ArcLineMaterial`] to widen, with texture coordinates along it in world units.
+    ///
+    /// `styles` holds the linear color and width multiple at each point.
+    fn push_line(&mut self, path: &ArcPath, styles: &[([f32; 4], f32)]) {
+        let points = &path.points;
+        for i in 0..points.len().saturating_sub(1) {
+            let (start, end) = (points[i], points[i + 1]);
+            // The points past each end, or the ends themselves where the line stops
+            let before = if i > 0 { points[i - 1] } else { start };
+            let after = points.get(i + 2).copied().unwrap_or(end);
+
+            let base_index = self.vertices.len() as u32;
+            self.vertices.extend_from_slice(&[start, start, end, end]);
+            self.others.extend_from_slice(&[end, end, start, start]);
+            self.neighbors
+                .extend_from_slice(&[before, before, after, after]);
+            self.corners
+                .extend_from_slice(&[[-1.0, 0.0], [1.0, 0.0], [1.0, 1.0], [-1.0, 1.0]]);
+            let ((start_color, start_width), (end_color, end_width)) = (styles[i], styles[i + 1]);
+            self.colors
+                .extend_from_slice(&[start_color, start_color, end_color, end_color]);
+            self.widths
+                .extend_from_slice(&[start_width, start_width, end_width, end_width]);
+            self.indices.extend_from_slice(&[
+                base_index,
+                base_index + 1,
+                base_index + 2,
+                base_index,
+                base_index + 2,
+                base_index + 3,
+            ]);
+        }
+        self.uvs.extend(line_uvs(&path.distances));
+    }
+
+    /// The merged mesh, or `None` if there are no lines with at least two points to draw, as
+    /// a mesh without vertices can't be rendered
+    fn into_mesh(self) -> Option<Mesh> {
+        if self.vertices.is_empty() {
+            return None;
+        }
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_indices(Some(mesh::Indices::U32(self.indices)));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.vertices);
+        mesh.insert_attribute(ATTRIBUTE_OTHER, self.others);
+        mesh.insert_attribute(ATTRIBUTE_NEIGHBOR, self.neighbors);
+        mesh.insert_attribute(ATTRIBUTE_CORNER, self.corners);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.insert_attribute(ATTRIBUTE_WIDTH, self.widths);
+        Some(mesh)
+    }
+}
+
+/// Create the mesh of a single arc line with the given color and width multiple at each point,
+/// and texture coordinates along it in world units, or `None` if the path is too short to draw
+fn create_line_mesh(path: &ArcPath, styles: &[([f32; 4], f32)]) -> Option<Mesh> {
+    let mut geometry = LineGeometry::default();
+    geometry.push_line(path, styles);
+    geometry.into_mesh()
+}
+
+/// Create a closed tube of `radius` around a path, with `radial_segments` vertices around each
+/// ring and flat caps at both ends. `styles` holds the linear color at each point, and the
+/// multiple of the radius there.
+///
+/// Each ring's orientation is carried along from the previous one by parallel transport,
+/// rotating it only as much as the path turns, so the tube doesn't twist. Texture coordinates
+/// run along the tube in world units and around it from 0 to 1. Paths of fewer than two
+/// points have no tube, and give `None`.
+fn create_tube_mesh(
+    path: &ArcPath,
+    styles: &[([f32; 4], f32)],
+    radius: f32,
+    radial_segments: u32,
+) -> Option<Mesh> {
+    let points = &path.points;
+    if points.len() < 2 {
+        return None;
+    }
+    let radial_segments = radial_segments.max(3);
+
+    // Tangents halfway between the segments meeting at each point
+    let mut tangents: Vec<Vec3> = Vec::with_capacity(points.len());
+    for i in 0..points.len() {
+        let before = points[i.saturating_sub(1)];
+        let after = points[(i + 1).min(points.len() - 1)];
+        let tangent = (after - before).normalize_or_zero();
+        let fallback = tangents.last().copied().unwrap_or(Vec3::X);
+        tangents.push(if tangent == Vec3::ZERO {
+            fallback
+        } else {
+            tangent
+        });
+    }
+    // Starts facing away from the globe where it can
+    let up = points[0].normalize_or_zero();
+    let mut normal = (up - tangents[0] * up.dot(tangents[0])).normalize_or_zero();
+    if normal == Vec3::ZERO {
+        normal = tangents[0].any_orthonormal_vector();
+    }
+
+    let ring = radial_segments as usize + 1; // The seam is doubled for the texture coordinates
+    let mut vertices = Vec::with_capacity(points.len() * ring + 2 * (ring + 1));
+    let mut normals = Vec::with_capacity(vertices.capacity());
+    let mut uvs = Vec::with_capacity(vertices.capacity());
+    let mut colors = Vec::with_capacity(vertices.capacity());
+    let mut frames = Vec::with_capacity(points.len());
+    for (i, point) in points.iter().enumerate() {
+        if i > 0 {
+            normal = Quat::from_rotation_arc(tangents[i - 1], tangents[i]) * normal;
+            // Kept perpendicular against rounding errors
+            normal = (normal - tangents[i] * normal.dot(tangents[i])).normalize();
+        }
+        let binormal = tangents[i].cross(normal);
+        frames.push((normal, binormal));
+        for j in 0..ring {
+            let angle = j as f32 / radial_segments as f32 * std::f32::consts::TAU;
+            let direction = normal * angle.cos() + binormal * angle.sin();
+            vertices.push(*point + direction * radius * styles[i].1);
+            normals.push(direction);
+            uvs.push([path.distances[i], j as f32 / radial_segments as f32]);
+            colors.push(styles[i].0);
+        }
+    }
+
+    let mut indices = Vec::with_capacity((points.len() - 1) * radial_segments as usize * 6);
+    for i in 0..points.len() as u32 - 1 {
+        for j in 0..radial_segments {
+            let a = i * ring as u32 + j;
+            let b = a + ring as u32;
+            // Counter-clockwise seen from outside the tube
+            indices.extend_from_slice(&[a, a + 1, b, a + 1, b + 1, b]);
+        }
+    }
+
+    // Caps with their own vertices, so they're shaded flat
+    let ends = [(0, -1.0), (points.len() - 1, 1.0)];
+    for (i, facing) in ends {
+        let (normal, binormal) = frames[i];
+        let cap_normal = tangents[i] * facing;
+        let center = vertices.len() as u32;
+        vertices.push(points[i]);
+        normals.push(cap_normal);
+        uvs.push([path.distances[i], 0.5]);
+        colors.push(styles[i].0);
+        for j in 0..ring {
+            let angle = j as f32 / radial_segments as f32 * std::f32::consts::TAU;
+            let direction = normal * angle.cos() + binormal * angle.sin();
+            vertices.push(points[i] + direction * radius * styles[i].1);
+            normals.push(cap_normal);
+            uvs.push([path.distances[i], j as f32 / radial_segments as f32]);
+            colors.push(styles[i].0);
+        }
+        for j in 0..radial_segments {
+            let (first, second) = (center + 1 + j, center + 2 + j);
+            // Wound to face out of the tube's end
+            if facing > 0.0 {
+                indices.extend_from_slice(&[center, first, second]);
+            } else {
+                indices.extend_from_slice(&[center, second, first]);
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(mesh::Indices::U32(indices)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    Some(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arc_flow() {
+        let path = ArcPath::new(vec![Vec3::ZERO, Vec3::X * 3.0, Vec3::new(3.0, 4.0, 0.0)]);
+        assert_eq!(path.distances, vec![0.0, 3.0, 7.0]);
+        assert_eq!(path.length(), 7.0);
+        assert_eq!(line_uvs(&path.distances).len(), 8);
+
+        // Each segment's ends know the points past them, to join the segments
+        let mut geometry = LineGeometry::default();
+        geometry.push_line(&path, &[([1.0; 4], 1.0); 3]);
+        assert_eq!(geometry.vertices.len(), 8);
+        assert_eq!(geometry.others[0], path.points[1]);
+        assert_eq!(geometry.neighbors[0], path.points[0]);
+        assert_eq!(geometry.neighbors[2], path.points[2]);
+        assert_eq!(geometry.neighbors[4], path.points[0]);
+        assert_eq!(geometry.neighbors[6], path.points[2]);
+
+        // A mark at the start of a repeat moves forward along the line over time
+        let flow = ArcFlow::new(FlowPattern::Dashes)
+            .with_spacing(10.0)
+            .with_speed(5.0);
+        assert_eq!(flow.coordinate(5.0, 0.0), 0.5);
+        assert_eq!(flow.coordinate(7.5, 0.5), 0.5);
+        let reverse = flow.clone().with_direction(FlowDirection::Reverse);
+        assert_eq!(reverse.coordinate(5.0, 0.0), -0.5);
+        assert_eq!(reverse.coordinate(2.5, 0.5), -0.5);
+        // The material animates the same coordinate from the distance along the line
+        assert_eq!(flow.phase(0.5), 0.25);
+        assert_eq!(reverse.signed_spacing(), -10.0);
+
+        // Dashes fill the first half of each repeat and the trail shows through the rest
+        assert_eq!(flow.opacity(0.25, 0.5), 1.0);
+        assert_eq!(flow.opacity(0.75, 0.5), 0.2);
+        let particles = ArcFlow::new(FlowPattern::Particles).with_trail_opacity(0.0);
+        assert_eq!(particles.opacity(0.5, 0.5), 1.0);
+        assert_eq!(particles.opacity(0.5, 0.0), 0.0);
+        assert_eq!(particles.opacity(0.75, 0.5), 0.0);
+    }
+
+    #[test]
+    fn test_arc_tube() {
+        let arc = ArcLine::new(0.0, 0.0, 30.0, 40.0)
+            .unwrap()
+            .with_segments(20);
+        let path = arc.path();
+        let mesh = create_tube_mesh(&path, &arc.point_styles(&path), 2.0, 8).unwrap();
+        let Some(bevy::render::mesh::VertexAttributeValues::Float32x3(vertices)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("tube has no positions");
+        };
+        // Rings of 9 vertices at 21 points, and two caps of 10
+        assert_eq!(vertices.len(), 21 * 9 + 2 * 10);
+
+        // Every ring is round and square to the path
+        for (i, point) in path.points.iter().enumerate() {
+            let tangent =
+                (path.points[(i + 1).min(20)] - path.points[i.saturating_sub(1)]).normalize();
+            for vertex in &vertices[i * 9..(i + 1) * 9] {
+                let offset = Vec3::from(*vertex) - *point;
+                assert!((offset.length() - 2.0).abs() < 1e-3);
+                assert!(offset.dot(tangent).abs() < 1e-3);
+            }
+        }
+
+        // Sides and caps face the way their vertex normals point, so culling keeps them
+        let Some(bevy::render::mesh::VertexAttributeValues::Float32x3(normals)) =
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        else {
+            panic!("tube has no normals");
+        };
+        let indices: Vec<usize> = mesh.indices().unwrap().iter().collect();
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(vertices[triangle[i]]));
+            let face = (b - a).cross(c - a);
+            for &index in triangle {
+                assert!(face.dot(Vec3::from(normals[index])) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_arc_height() {
+        let profiles = [
+            ArcProfile::Parabolic,
+            ArcProfile::Sine,
+            ArcProfile::CubicBezier(4.0 / 3.0, 4.0 / 3.0),
+            ArcProfile::Cruise { ramp: 0.2 },
+        ];
+        for profile in profiles {
+            assert_eq!(profile.height(0.0), 0.0);
+            assert!(profile.height(1.0).abs() < 1e-6);
+            assert!((profile.height(0.5) - 1.0).abs() < 1e-6);
+        }
+        assert!((ArcProfile::CubicBezier(4.0 / 3.0, 4.0 / 3.0).height(0.3) - 0.84).abs() < 1e-6);
+        assert_eq!(ArcProfile::Cruise { ramp: 0.2 }.height(0.3), 1.0);
+
+        // Auto heights grow with distance, within their limits
+        let height = ArcHeight::Auto {
+            scale: 30.0,
+            min: 5.0,
+            max: 100.0,
+        };
+        assert_eq!(height.peak(0.01), 5.0);
+        assert_eq!(height.peak(1.0), 30.0);
+        let near = ArcLine::new(30.0, -97.0, 32.0, -96.0).unwrap();
+        let far = ArcLine::new(30.0, -97.0, 35.0, 139.0).unwrap();
+        let peak = |arc: ArcLine| {
+            let path = arc.clone().with_arc_height(0.0).with_segments(10).path();
+            let flat = path.points[5].length();
+            let path = arc.with_segments(10).path();
+            path.points[5].length() - flat
+        };
+        let (near, far) = (
+            peak(near.with_auto_height(30.0, 5.0, 100.0)),
+            peak(far.with_auto_height(30.0, 5.0, 100.0)),
+        );
+        assert!((near - 5.0).abs() < 1e-3);
+        assert!(far > 30.0 && far < 100.0);
+    }
+
+    #[test]
+    fn test_arc_progress() {
+        let path = ArcPath::new(vec![Vec3::ZERO, Vec3::X * 3.0, Vec3::new(3.0, 4.0, 0.0)]);
+        let half = path.truncated(3.5);
+        assert_eq!(
+            half.points,
+            vec![Vec3::ZERO, Vec3::X * 3.0, Vec3::new(3.0, 0.5, 0.0)]
+        );
+        assert_eq!(half.length(), 3.5);
+        assert_eq!(path.truncated(0.0).points.len(), 1);
+        assert_eq!(path.truncated(10.0).points, path.points);
+
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+        }
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+        assert!(Easing::EaseIn.apply(0.25) < 0.25 && Easing::EaseOut.apply(0.25) > 0.25);
+
+        // Styles are spread over the whole arc, however much of it is drawn
+        let arc = ArcLine::new(0.0, 0.0, 0.0, 90.0)
+            .unwrap()
+            .with_gradient(Color::RED, Color::BLUE)
+            .with_fade(0.25)
+            .with_taper(0.5)
+            .with_progress(0.5);
+        let (color, width) = arc.style_at(0.5);
+        assert_eq!(color.as_linear_rgba_f32(), [0.5, 0.0, 0.5, 1.0]);
+        assert_eq!(width, 0.75);
+        assert_eq!(arc.style_at(0.125).0.a(), 0.5);
+        let styles = arc.point_styles(&arc.path());
+        assert!((styles.last().unwrap().1 - 0.75).abs() < 1e-3);
+
+        // Nothing is drawn of an arc yet to be revealed, as a line or as a tube
+        let hidden = arc.with_progress(0.0);
+        let path = hidden.path();
+        let styles = hidden.point_styles(&path);
+        assert!(path.points.len() < 2);
+        assert!(create_line_mesh(&path, &styles).is_none());
+        assert!(create_tube_mesh(&path, &styles, 1.0, 8).is_none());
+
+        let mut reveal = ArcReveal::new(2.0)
+            .with_delay(1.0)
+            .with_easing(Easing::Linear);
+        reveal.elapsed = 0.5;
+        assert_eq!(reveal.progress(), 0.0);
+        reveal.elapsed = 2.0;
+        assert_eq!(reveal.progress(), 0.5);
+        assert!(!reveal.is_finished());
+        reveal.elapsed = 3.0;
+        assert!(reveal.is_finished() && reveal.progress() == 1.0);
+    }
+
+    #[test]
+    fn test_arc_layer() {
+        let arc = |longitude: f32| ArcLine::new(0.0, longitude, 0.0, longitude + 20.0).unwrap();
+        let mut layer = ArcLayer::new((0..5).map(|i| arc(i as f32 * 10.0))).with_chunk_size(2);
+        assert_eq!(layer.take_stale_chunks(), vec![0, 1, 2]);
+        assert_eq!(layer.chunk_range(2), 4..5);
+
+        // Only the chunks of changed arcs are rebuilt
+        assert!(layer.take_stale_chunks().is_empty());
+        layer.set(3, arc(100.0).with_color(Color::RED));
+        assert_eq!(layer.push(arc(120.0)), 5);
+        assert_eq!(layer.take_stale_chunks(), vec![1, 2]);
+
+        // Arcs are picked where a ray passes close to them
+        let path = layer.get(3).unwrap().path();
+        let peak = path.points[path.points.len() / 2];
+        let ray = Ray {
+            origin: peak * 2.0,
+            direction: -peak.normalize(),
+        };
+        let t = pick_path(&ray, &path, 0.01).unwrap();
+        assert!((t - peak.length()).abs() < 1e-2);
+        let miss = Ray {
+            origin: peak * 2.0 + Vec3::Y * 5.0,
+            direction: -peak.normalize(),
+        };
+        assert!(pick_path(&miss, &path, 0.01).is_none());
+
+        // Picking follows the layer's transform
+        let chunk = ArcChunk {
+            layer: Entity::PLACEHOLDER,
+            first: 2,
+            bounds: layer.arcs[2..4]
+                .iter()
+                .map(|arc| path_bounds(&arc.path()))
+                .collect(),
+        };
+        let moved = Transform::from_scale(Vec3::splat(2.0)).with_translation(Vec3::X * 10.0);
+        let world_ray = Ray {
+            origin: moved.transform_point(ray.origin),
+            direction: ray.direction,
+        };
+        let (index, t) = pick_chunk(&world_ray, &chunk, &layer, &moved.into(), 0.01).unwrap();
+        assert_eq!(index, 3);
+        assert!((t - 2.0 * peak.length()).abs() < 2e-2);
+        assert!(pick_chunk(&ray, &chunk, &layer, &moved.into(), 0.01).is_none());
+    }
+}
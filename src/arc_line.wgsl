@@ -0,0 +1,145 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+
+struct ArcLineMaterial {
+    color: vec4<f32>,
+    width: f32,
+    miter_limit: f32,
+    flow_spacing: f32,
+    flow_phase: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> material: ArcLineMaterial;
+@group(1) @binding(1)
+var flow_texture: texture_2d<f32>;
+@group(1) @binding(2)
+var flow_sampler: sampler;
+
+// Lines are widened by this many pixels on each side to fade their edges out
+const ANTI_ALIASING: f32 = 1.0;
+
+struct Vertex {
+    // End of the segment this vertex belongs to
+    @location(0) position: vec3<f32>,
+    // The segment's other end
+    @location(1) other: vec3<f32>,
+    // The next point along the line past this end, or this end again at the end of the line
+    @location(2) neighbor: vec3<f32>,
+    // Side of the line, -1 or 1, and whether this is the segment's end rather than its start
+    @location(3) corner: vec2<f32>,
+    @location(4) uv: vec2<f32>,
+    @location(5) color: vec4<f32>,
+    // Width at this vertex as a multiple of the material's, to taper lines
+    @location(6) width: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) uv: vec2<f32>,
+    // Signed distance from the middle of the line in pixels
+    @location(2) @interpolate(linear) across: f32,
+    // Screen position in pixels, and the segment's start and end, for round joins
+    @location(3) @interpolate(linear) pixel: vec2<f32>,
+    @location(4) @interpolate(flat) segment: vec4<f32>,
+    // Width of the line here in pixels
+    @location(5) @interpolate(linear) width: f32,
+};
+
+fn to_clip(position: vec3<f32>) -> vec4<f32> {
+    return mesh_position_local_to_clip(mesh.model, vec4<f32>(position, 1.0));
+}
+
+// Pixels from the center of the viewport
+fn to_screen(clip: vec4<f32>) -> vec2<f32> {
+    return clip.xy / max(clip.w, 0.0001) * view.viewport.zw * 0.5;
+}
+
+fn safe_normalize(v: vec2<f32>) -> vec2<f32> {
+    let size = length(v);
+    if size < 0.0001 {
+        return vec2<f32>(0.0, 0.0);
+    }
+    return v / size;
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let clip = to_clip(vertex.position);
+    let here = to_screen(clip);
+    let other = to_screen(to_clip(vertex.other));
+    let neighbor = to_screen(to_clip(vertex.neighbor));
+    let side = vertex.corner.x;
+    let is_end = vertex.corner.y > 0.5;
+
+    // Along the line from the segment's start to its end, and to its left
+    var forward = safe_normalize(other - here);
+    if is_end {
+        forward = -forward;
+    }
+    let normal = vec2<f32>(-forward.y, forward.x);
+    let width = material.width * vertex.width;
+    let half_width = width * 0.5 + ANTI_ALIASING;
+
+    var out: VertexOutput;
+#ifdef ROUND_JOINS
+    // Extended past both ends, and rounded off in the fragment shader, so segments overlap in
+    // round joins
+    var along = -1.0;
+    out.segment = vec4<f32>(here, other);
+    if is_end {
+        along = 1.0;
+        out.segment = vec4<f32>(other, here);
+    }
+    let offset = (normal * side + forward * along) * half_width;
+#else
+    // Both segments meeting at a point offset it along the bisector of their directions, by as
+    // much as keeps them the same width, up to the miter limit
+    var adjacent = here - neighbor;
+    if is_end {
+        adjacent = neighbor - here;
+    }
+    var miter = normal;
+    let tangent = safe_normalize(safe_normalize(adjacent) + forward);
+    if length(adjacent) > 0.0001 && length(tangent) > 0.0 {
+        miter = vec2<f32>(-tangent.y, tangent.x);
+    }
+    let scale = 1.0 / max(dot(miter, normal), 1.0 / material.miter_limit);
+    let offset = miter * side * half_width * scale;
+    out.segment = vec4<f32>(0.0);
+#endif
+
+    out.clip_position = clip + vec4<f32>(offset / (view.viewport.zw * 0.5) * clip.w, 0.0, 0.0);
+    out.color = vertex.color;
+    out.uv = vertex.uv;
+    out.across = side * half_width;
+    out.pixel = here + offset;
+    out.width = width;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef ROUND_JOINS
+    let start = in.segment.xy;
+    let direction = in.segment.zw - start;
+    let t = clamp(dot(in.pixel - start, direction) / max(dot(direction, direction), 0.0001), 0.0, 1.0);
+    let distance = length(in.pixel - (start + direction * t));
+#else
+    let distance = abs(in.across);
+#endif
+    // Fraction of the pixel covered by the line
+    let coverage = clamp(in.width * 0.5 + 0.5 - distance, 0.0, 1.0);
+    // Texture coordinates run along the line in world units
+    let flow_uv = vec2<f32>(in.uv.x / material.flow_spacing - material.flow_phase, in.uv.y);
+    var color = material.color * in.color * textureSample(flow_texture, flow_sampler, flow_uv);
+    color.a *= coverage;
+    if color.a <= 0.0 {
+        discard;
+    }
+    return color;
+}
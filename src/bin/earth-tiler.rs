@@ -0,0 +1,321 @@
+//! Raster tooling for preparing and inspecting elevation data.
+//!
+//! `earth-tiler tile` preprocesses a GeoTIFF into cube-sphere height tiles for the runtime to
+//! load, `earth-tiler raster-info` describes a raster and queries elevations from it, and
+//! `earth-tiler export` writes the displaced globe out as a glTF, OBJ or STL model.
+
+use std::fs::File;
+use std::io::{BufRead, BufWriter};
+use std::path::Path;
+use std::process::ExitCode;
+
+use bevy_earth::export::{generate_globe, ExportRegion, TerrainExport};
+use bevy_earth::map::open_height_source_with_detail;
+use bevy_earth::raster::RasterData;
+use bevy_earth::tiles::{HeightTile, TileId, TileSet, CUBE_FACES, MAX_LEVEL, MAX_TILE_SIZE};
+
+const USAGE: &str = "Usage:
+  earth-tiler tile <INPUT> <OUTPUT_DIR> [--max-level N] [--tile-size N]
+  earth-tiler raster-info <INPUT> [--bins N] [--query]
+  earth-tiler export <ELEVATION> <OUTPUT> [--resolution N] [--exaggeration X]
+                     [--region MIN_LAT,MIN_LON,MAX_LAT,MAX_LON] [--texture URI]
+
+raster-info --query reads `latitude longitude` pairs from stdin, one per line, and prints the
+elevation at each.
+
+export picks the format from the OUTPUT extension: .gltf, .obj or .stl. ELEVATION is a raster
+or a tile directory written by `tile`.";
+
+struct TileArgs {
+    input: String,
+    output: String,
+    max_level: u8,
+    tile_size: u32,
+}
+
+struct InfoArgs {
+    input: String,
+    bins: usize,
+    query: bool,
+}
+
+struct ExportArgs {
+    elevation: String,
+    output: String,
+    resolution: u32,
+    exaggeration: f64,
+    region: Option<ExportRegion>,
+    texture: Option<String>,
+}
+
+enum Command {
+    Tile(TileArgs),
+    RasterInfo(InfoArgs),
+    Export(ExportArgs),
+}
+
+fn parse_region(value: &str) -> Option<ExportRegion> {
+    let values: Vec<f32> = value
+        .split(',')
+        .map(|part| part.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    match values[..] {
+        [min_latitude, min_longitude, max_latitude, max_longitude] => Some(ExportRegion {
+            min_latitude,
+            min_longitude,
+            max_latitude,
+            max_longitude,
+        }),
+        _ => None,
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let command = args.next().ok_or(USAGE)?;
+    let mut positional = Vec::new();
+    let mut max_level = 3;
+    let mut tile_size = 257;
+    let mut bins = 10;
+    let mut query = false;
+    let mut resolution = 600;
+    let mut exaggeration = 1.0 / 300.0;
+    let mut region = None;
+    let mut texture = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-level" => {
+                max_level = args
+                    .next()
+                    .and_then(|value| value.parse::<u8>().ok())
+                    .filter(|level| *level <= MAX_LEVEL)
+                    .ok_or(format!("--max-level expects a number up to {MAX_LEVEL}"))?;
+            }
+            "--tile-size" => {
+                tile_size = args
+                    .next()
+                    .and_then(|value| value.parse::<u32>().ok())
+                    .filter(|size| (2..=MAX_TILE_SIZE).contains(size))
+                    .ok_or(format!(
+                        "--tile-size expects a number from 2 to {MAX_TILE_SIZE}"
+                    ))?;
+            }
+            "--bins" => {
+                bins = args
+                    .next()
+                    .and_then(|value| value.parse::<usize>().ok())
+                    .filter(|bins| *bins >= 1)
+                    .ok_or("--bins expects a positive number")?;
+            }
+            "--query" => query = true,
+            "--resolution" => {
+                resolution = args
+                    .next()
+                    .and_then(|value| value.parse::<u32>().ok())
+                    .filter(|resolution| *resolution >= 2)
+                    .ok_or("--resolution expects a number of at least 2")?;
+            }
+            "--exaggeration" => {
+                exaggeration = args
+                    .next()
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .ok_or("--exaggeration expects world units per metre")?;
+            }
+            "--region" => {
+                region = Some(
+                    args.next()
+                        .as_deref()
+                        .and_then(parse_region)
+                        .ok_or("--region expects MIN_LAT,MIN_LON,MAX_LAT,MAX_LON")?,
+                );
+            }
+            "--texture" => texture = Some(args.next().ok_or("--texture expects a URI")?),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {arg}\n{USAGE}")),
+            _ => positional.push(arg),
+        }
+    }
+
+    match (command.as_str(), <[String; 2]>::try_from(positional)) {
+        ("tile", Ok([input, output])) => Ok(Command::Tile(TileArgs {
+            input,
+            output,
+            max_level,
+            tile_size,
+        })),
+        ("export", Ok([elevation, output])) => Ok(Command::Export(ExportArgs {
+            elevation,
+            output,
+            resolution,
+            exaggeration,
+            region,
+            texture,
+        })),
+        ("raster-info", Err(positional)) if positional.len() == 1 => {
+            Ok(Command::RasterInfo(InfoArgs {
+                input: positional[0].clone(),
+                bins,
+                query,
+            }))
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() -> ExitCode {
+    let result = match parse_args(std::env::args().skip(1)) {
+        Ok(Command::Tile(args)) => tile(args),
+        Ok(Command::RasterInfo(args)) => raster_info(args),
+        Ok(Command::Export(args)) => export(args),
+        Err(msg) => Err(msg),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(msg) => {
+            eprintln!("{msg}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn tile(args: TileArgs) -> Result<(), String> {
+    let raster = RasterData::new(&args.input)
+        .map_err(|err| format!("Failed to open {}: {err}", args.input))?;
+    // Four faces wrap the equator, so this is enough pixels for the deepest level
+    let max_width = 4 * (1 << args.max_level) * (args.tile_size as usize - 1);
+    let grid = raster
+        .read_grid(max_width)
+        .map_err(|err| format!("Failed to read {}: {err}", args.input))?;
+
+    for face in 0..CUBE_FACES.len() as u8 {
+        let mut level_tiles = vec![TileId::new(face, 0, 0, 0)];
+        for level in 0..=args.max_level {
+            println!("Face {face}, level {level}: {} tiles", level_tiles.len());
+            for id in &level_tiles {
+                HeightTile::generate(*id, args.tile_size, &grid)
+                    .save(&args.output)
+                    .map_err(|err| format!("Failed to write tile {id:?}: {err}"))?;
+            }
+            level_tiles = level_tiles.iter().flat_map(TileId::children).collect();
+        }
+    }
+    // Written last, so the tile set only changes generation once all its tiles are in place
+    TileSet::write_manifest(&args.output, args.tile_size, args.max_level)
+        .map_err(|err| format!("Failed to write to {}: {err}", args.output))
+}
+
+fn raster_info(args: InfoArgs) -> Result<(), String> {
+    let raster = RasterData::new(&args.input)
+        .map_err(|err| format!("Failed to open {}: {err}", args.input))?;
+
+    if args.query {
+        return query_elevations(&raster);
+    }
+
+    let info = raster
+        .info()
+        .map_err(|err| format!("Failed to read {}: {err}", args.input))?;
+    let (min_x, min_y, max_x, max_y) = info.bounds;
+    println!("File:       {}", args.input);
+    println!("Driver:     {}", info.driver);
+    println!("Size:       {} x {} pixels", info.size.0, info.size.1);
+    println!("CRS:        {}", info.crs);
+    println!("Bounds:     x {min_x} to {max_x}, y {min_y} to {max_y}");
+    println!(
+        "Resolution: {} x {}",
+        info.resolution.0,
+        info.resolution.1.abs()
+    );
+
+    for band in info.bands {
+        println!();
+        println!("Band {}: {}", band.index, band.data_type);
+        match band.no_data {
+            Some(no_data) => println!("  NoData: {no_data}"),
+            None => println!("  NoData: none"),
+        }
+        let stats = raster
+            .band_statistics(band.index, args.bins)
+            .map_err(|err| format!("Failed to read band {}: {err}", band.index))?;
+        if stats.valid_count == 0 {
+            println!("  No valid pixels");
+            continue;
+        }
+        println!("  Min:    {}", stats.min);
+        println!("  Max:    {}", stats.max);
+        println!("  Mean:   {}", stats.mean);
+        println!(
+            "  Pixels: {} valid, {} NoData",
+            stats.valid_count, stats.no_data_count
+        );
+        println!("  Histogram:");
+        let bin_width = (stats.max - stats.min) / stats.histogram.len() as f64;
+        for (i, count) in stats.histogram.iter().enumerate() {
+            let low = stats.min + bin_width * i as f64;
+            println!("    {low:>12.2} .. {:<12.2} {count}", low + bin_width);
+        }
+    }
+    Ok(())
+}
+
+fn query_elevations(raster: &RasterData) -> Result<(), String> {
+    for line in std::io::stdin().lock().lines() {
+        let line = line.map_err(|err| format!("Failed to read stdin: {err}"))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .map(str::parse::<f64>);
+        let (latitude, longitude) = match (parts.next(), parts.next()) {
+            (Some(Ok(latitude)), Some(Ok(longitude))) => (latitude, longitude),
+            _ => {
+                eprintln!("Skipping line, expected `latitude longitude`: {line}");
+                continue;
+            }
+        };
+        match raster.get_coordinate_height(latitude, longitude) {
+            Ok(Some(elevation)) => println!("{latitude} {longitude} {elevation}"),
+            Ok(None) => println!("{latitude} {longitude} nodata"),
+            Err(err) => println!("{latitude} {longitude} error: {err}"),
+        }
+    }
+    Ok(())
+}
+
+fn export(args: ExportArgs) -> Result<(), String> {
+    let extension = Path::new(&args.output)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    if !matches!(extension.as_deref(), Some("gltf" | "obj" | "stl")) {
+        return Err(format!(
+            "Unsupported export format for {}, expected .gltf, .obj or .stl",
+            args.output
+        ));
+    }
+
+    // Patches span half a cube face
+    let rs = open_height_source_with_detail(&args.elevation, 2 * (args.resolution - 1))?;
+    println!("Generating terrain at resolution {}", args.resolution);
+    let meshes = generate_globe(args.resolution, args.exaggeration, rs.as_ref());
+    let terrain = TerrainExport::from_meshes(&meshes, args.region.as_ref());
+    println!(
+        "Writing {} vertices and {} triangles to {}",
+        terrain.positions.len(),
+        terrain.indices.len() / 3,
+        args.output
+    );
+
+    let create = || {
+        File::create(&args.output)
+            .map(BufWriter::new)
+            .map_err(|err| format!("Failed to create {}: {err}", args.output))
+    };
+    let result = match extension.as_deref() {
+        Some("gltf") => terrain.write_gltf(&args.output, args.texture.as_deref()),
+        Some("obj") => terrain.write_obj(create()?),
+        _ => terrain.write_stl(create()?),
+    };
+    result.map_err(|err| format!("Failed to write {}: {err}", args.output))
+}
@@ -0,0 +1,295 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
+
+use crate::tiles::MANIFEST;
+
+const MAGIC: &[u8; 4] = b"BEMC";
+const FORMAT_VERSION: u32 = 1;
+
+/// Most elements reserved up front from the counts in a file's header, so a corrupt count
+/// fails when the data runs out instead of aborting on a huge allocation
+const MAX_RESERVED: usize = 1 << 20;
+
+/// On-disk cache of generated terrain patch meshes.
+///
+/// Entries are keyed by a hash of the elevation dataset, the patch resolution and the elevation
+/// exaggeration, so changing any of them makes the old entries miss instead of loading stale
+/// geometry.
+pub struct MeshCache {
+    dir: PathBuf,
+    key: String,
+}
+
+impl MeshCache {
+    pub fn new(
+        dir: impl AsRef<Path>,
+        dataset: impl AsRef<Path>,
+        resolution: u32,
+        exaggeration: f64,
+    ) -> io::Result<Self> {
+        let key = format!(
+            "{:016x}_{}_{:016x}",
+            dataset_hash(dataset)?,
+            resolution,
+            exaggeration.to_bits()
+        );
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            key,
+        })
+    }
+
+    fn patch_path(&self, normal: Vec3, x_offset: f32, y_offset: f32) -> PathBuf {
+        let name = format!(
+            "{}_{}_{}_{}_{}_{}.mesh",
+            self.key, normal.x, normal.y, normal.z, x_offset, y_offset
+        );
+        self.dir.join(name)
+    }
+
+    /// Returns the cached mesh for a patch, or `None` if it is missing or unreadable
+    pub fn load(&self, normal: Vec3, x_offset: f32, y_offset: f32) -> Option<Mesh> {
+        let file = File::open(self.patch_path(normal, x_offset, y_offset)).ok()?;
+        read_mesh(BufReader::new(file)).ok()
+    }
+
+    pub fn store(&self, normal: Vec3, x_offset: f32, y_offset: f32, mesh: &Mesh) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.patch_path(normal, x_offset, y_offset);
+        // Write to a temporary file first so an interrupted run never leaves a truncated entry
+        let tmp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        write_mesh(&mut writer, mesh)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(tmp_path, path)
+    }
+}
+
+/// Cheap fingerprint of a dataset file from its path, size and modification time.
+///
+/// Hashing the contents of a global raster would take longer than the cache saves, and a
+/// replaced file virtually always changes size or mtime. A tile directory's own metadata
+/// doesn't change when its tiles are rewritten, so its manifest's contents are hashed instead.
+pub fn dataset_hash(path: impl AsRef<Path>) -> io::Result<u64> {
+    let path = path.as_ref();
+    let metadata = fs::metadata(path)?;
+
+    let mut hash = Fnv1a::new();
+    hash.write(fs::canonicalize(path)?.to_string_lossy().as_bytes());
+    if metadata.is_dir() {
+        hash.write(&fs::read(path.join(MANIFEST))?);
+    } else {
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        hash.write(&metadata.len().to_le_bytes());
+        hash.write(&modified.as_secs().to_le_bytes());
+        hash.write(&modified.subsec_nanos().to_le_bytes());
+    }
+    Ok(hash.finish())
+}
+
+/// FNV-1a, used instead of `DefaultHasher` because its output must be stable across builds
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Serialize a triangle-list mesh's positions, normals, UVs, tangents and indices
+pub fn write_mesh(mut writer: impl Write, mesh: &Mesh) -> io::Result<()> {
+    let positions = float32x3_attribute(mesh, Mesh::ATTRIBUTE_POSITION)?;
+    let normals = float32x3_attribute(mesh, Mesh::ATTRIBUTE_NORMAL)?;
+    let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(VertexAttributeValues::Float32x2(values)) => values,
+        _ => return Err(invalid_data("mesh has no Float32x2 UVs")),
+    };
+    let tangents = match mesh.attribute(Mesh::ATTRIBUTE_TANGENT) {
+        Some(VertexAttributeValues::Float32x4(values)) => Some(values),
+        _ => None,
+    };
+    let indices = match mesh.indices() {
+        Some(Indices::U32(indices)) => indices,
+        _ => return Err(invalid_data("mesh has no u32 indices")),
+    };
+    if normals.len() != positions.len() || uvs.len() != positions.len() {
+        return Err(invalid_data("mesh attributes have mismatched lengths"));
+    }
+
+    writer.write_all(MAGIC)?;
+    write_u32(&mut writer, FORMAT_VERSION)?;
+    write_u32(&mut writer, positions.len() as u32)?;
+    write_u32(&mut writer, indices.len() as u32)?;
+    write_u32(&mut writer, tangents.is_some() as u32)?;
+    for value in positions.iter().chain(normals).flatten() {
+        write_f32(&mut writer, *value)?;
+    }
+    for value in uvs.iter().flatten() {
+        write_f32(&mut writer, *value)?;
+    }
+    if let Some(tangents) = tangents {
+        for value in tangents.iter().flatten() {
+            write_f32(&mut writer, *value)?;
+        }
+    }
+    for index in indices {
+        write_u32(&mut writer, *index)?;
+    }
+    Ok(())
+}
+
+/// Deserialize a mesh written by [`write_mesh`]
+pub fn read_mesh(mut reader: impl Read) -> io::Result<Mesh> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a terrain mesh cache file"));
+    }
+    if read_u32(&mut reader)? != FORMAT_VERSION {
+        return Err(invalid_data("unsupported terrain mesh cache version"));
+    }
+    let vertex_count = read_u32(&mut reader)? as usize;
+    let index_count = read_u32(&mut reader)? as usize;
+    let has_tangents = read_u32(&mut reader)? != 0;
+
+    let positions = read_vec3s(&mut reader, vertex_count)?;
+    let normals = read_vec3s(&mut reader, vertex_count)?;
+    let mut uvs = Vec::with_capacity(vertex_count.min(MAX_RESERVED));
+    for _ in 0..vertex_count {
+        uvs.push([read_f32(&mut reader)?, read_f32(&mut reader)?]);
+    }
+    let mut tangents = Vec::new();
+    if has_tangents {
+        tangents.reserve(vertex_count.min(MAX_RESERVED));
+        for _ in 0..vertex_count {
+            let mut tangent = [0.0; 4];
+            for value in &mut tangent {
+                *value = read_f32(&mut reader)?;
+            }
+            tangents.push(tangent);
+        }
+    }
+    let mut indices = Vec::with_capacity(index_count.min(MAX_RESERVED));
+    for _ in 0..index_count {
+        let index = read_u32(&mut reader)?;
+        if index as usize >= vertex_count {
+            return Err(invalid_data("index out of range"));
+        }
+        indices.push(index);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    if has_tangents {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents);
+    }
+    Ok(mesh)
+}
+
+fn float32x3_attribute(
+    mesh: &Mesh,
+    attribute: impl Into<bevy::render::mesh::MeshVertexAttributeId>,
+) -> io::Result<&Vec<[f32; 3]>> {
+    match mesh.attribute(attribute) {
+        Some(VertexAttributeValues::Float32x3(values)) => Ok(values),
+        _ => Err(invalid_data("mesh is missing a Float32x3 attribute")),
+    }
+}
+
+fn read_vec3s(reader: &mut impl Read, count: usize) -> io::Result<Vec<[f32; 3]>> {
+    let mut values = Vec::with_capacity(count.min(MAX_RESERVED));
+    for _ in 0..count {
+        values.push([read_f32(reader)?, read_f32(reader)?, read_f32(reader)?]);
+    }
+    Ok(values)
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_f32(writer: &mut impl Write, value: f32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{generate_face, FlatSurface};
+
+    #[test]
+    fn test_mesh_round_trip() {
+        let mesh = generate_face(Vec3::X, 8, 0.0, 1.0, 1.0, &FlatSurface);
+        let mut bytes = Vec::new();
+        write_mesh(&mut bytes, &mesh).unwrap();
+        let loaded = read_mesh(bytes.as_slice()).unwrap();
+
+        for attribute in [
+            Mesh::ATTRIBUTE_POSITION,
+            Mesh::ATTRIBUTE_NORMAL,
+            Mesh::ATTRIBUTE_UV_0,
+            Mesh::ATTRIBUTE_TANGENT,
+        ] {
+            assert_eq!(
+                mesh.attribute(attribute.id).unwrap().get_bytes(),
+                loaded.attribute(attribute.id).unwrap().get_bytes()
+            );
+        }
+        assert_eq!(
+            mesh.indices().unwrap().iter().collect::<Vec<_>>(),
+            loaded.indices().unwrap().iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_truncated_mesh_is_rejected() {
+        let mesh = generate_face(Vec3::Y, 4, 0.0, 0.0, 1.0, &FlatSurface);
+        let mut bytes = Vec::new();
+        write_mesh(&mut bytes, &mesh).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(read_mesh(bytes.as_slice()).is_err());
+
+        // A corrupt vertex count fails on the missing data rather than allocating for it
+        bytes[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_mesh(bytes.as_slice()).is_err());
+    }
+}
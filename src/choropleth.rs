@@ -0,0 +1,422 @@
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+use crate::labels::LabelSettings;
+use crate::polygons::{GeoFeature, PolygonLayer};
+use crate::scale::{ColorScale, Palette};
+
+/// Stops shown in the legend of a continuous scale
+const CONTINUOUS_LEGEND_STOPS: usize = 5;
+
+/// How property values are grouped before coloring
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Classification {
+    /// Every value gets its own color along the scale
+    Continuous,
+    /// This many classes of equal width across the scale's domain
+    EqualInterval(usize),
+    /// This many classes holding roughly the same number of features each
+    Quantile(usize),
+    /// Values are treated as text, with one color per distinct value taken in turn from the
+    /// scale's colors
+    Categorical,
+}
+
+/// Colors the fills of a [`PolygonLayer`] by one of its feature properties.
+///
+/// Features without the property, or with a value that isn't a number for numeric
+/// classifications, get `no_data_color`.
+#[derive(Component, Clone, Debug)]
+pub struct Choropleth {
+    pub property: String,
+    pub scale: ColorScale,
+    pub classification: Classification,
+    pub no_data_color: Color,
+    /// Legend heading, or `None` to show no legend
+    pub legend_title: Option<String>,
+}
+
+impl Choropleth {
+    pub fn new(property: &str) -> Self {
+        Self {
+            property: property.to_string(),
+            scale: ColorScale::new(Palette::Viridis),
+            classification: Classification::Continuous,
+            no_data_color: Color::GRAY,
+            legend_title: Some(property.to_string()),
+        }
+    }
+
+    pub fn with_scale(mut self, scale: ColorScale) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_classification(mut self, classification: Classification) -> Self {
+        self.classification = classification;
+        self
+    }
+
+    pub fn with_no_data_color(mut self, color: Color) -> Self {
+        self.no_data_color = color;
+        self
+    }
+
+    pub fn with_legend_title(mut self, title: Option<&str>) -> Self {
+        self.legend_title = title.map(str::to_string);
+        self
+    }
+
+    /// Fits the scale to the values of every feature in the layer
+    pub fn fit<'a>(&self, values: impl IntoIterator<Item = Option<&'a str>>) -> ChoroplethScale {
+        let values: Vec<&str> = values.into_iter().flatten().map(str::trim).collect();
+        let scale = self.scale.clone();
+        if self.classification == Classification::Categorical {
+            let mut categories: Vec<String> = values
+                .iter()
+                .filter(|value| !value.is_empty())
+                .map(|value| value.to_string())
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            categories.sort();
+            return ChoroplethScale {
+                scale,
+                no_data_color: self.no_data_color,
+                domain: Domain::Categories(categories),
+            };
+        }
+
+        let mut numbers: Vec<f64> = values
+            .iter()
+            .filter_map(|value| value.parse().ok())
+            .filter(|value: &f64| value.is_finite())
+            .collect();
+        numbers.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let scale = scale.fit(numbers.iter().map(|value| *value as f32));
+        let (min, max) = scale.domain.unwrap_or_default();
+        let (min, max) = (min as f64, max as f64);
+        let breaks = match self.classification {
+            Classification::EqualInterval(classes) => equal_interval_breaks(min, max, classes),
+            Classification::Quantile(classes) => quantile_breaks(&numbers, classes),
+            Classification::Continuous | Classification::Categorical => Vec::new(),
+        };
+        ChoroplethScale {
+            scale,
+            no_data_color: self.no_data_color,
+            domain: Domain::Numeric { min, max, breaks },
+        }
+    }
+}
+
+/// Inner class boundaries splitting `min..=max` into classes of equal width
+pub fn equal_interval_breaks(min: f64, max: f64, classes: usize) -> Vec<f64> {
+    (1..classes.max(1))
+        .map(|i| min + (max - min) * i as f64 / classes as f64)
+        .collect()
+}
+
+/// Inner class boundaries putting roughly the same number of sorted values in each class
+pub fn quantile_breaks(sorted: &[f64], classes: usize) -> Vec<f64> {
+    if sorted.is_empty() {
+        return Vec::new();
+    }
+    let mut breaks: Vec<f64> = (1..classes.max(1))
+        .map(|i| sorted[(sorted.len() * i / classes).min(sorted.len() - 1)])
+        .collect();
+    // Repeated values can make neighbouring quantiles equal, which would leave empty classes
+    breaks.dedup();
+    breaks
+}
+
+#[derive(Clone, Debug)]
+enum Domain {
+    Numeric {
+        min: f64,
+        max: f64,
+        breaks: Vec<f64>,
+    },
+    Categories(Vec<String>),
+}
+
+/// A [`Choropleth`] fitted to a layer's values
+#[derive(Clone, Debug)]
+pub struct ChoroplethScale {
+    scale: ColorScale,
+    no_data_color: Color,
+    domain: Domain,
+}
+
+/// A row of a legend: a swatch color and the values it stands for
+#[derive(Clone, Debug, PartialEq)]
+pub struct LegendEntry {
+    pub color: Color,
+    pub label: String,
+}
+
+fn format_value(value: f64) -> String {
+    if value.abs() >= 100.0 || value.fract() == 0.0 {
+        format!("{value:.0}")
+    } else {
+        let text = format!("{value:.2}");
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+impl ChoroplethScale {
+    /// Value ranges of each class, from the smallest values up
+    fn classes(&self) -> Vec<(f64, f64)> {
+        let Domain::Numeric { min, max, breaks } = &self.domain else {
+            return Vec::new();
+        };
+        let mut bounds = vec![*min];
+        bounds.extend(breaks);
+        bounds.push(*max);
+        bounds.windows(2).map(|pair| (pair[0], pair[1])).collect()
+    }
+
+    /// Color of the `index`th of `count` classes.
+    ///
+    /// Classes are shaded at evenly spaced positions from one end of the domain to the other,
+    /// so the classes at either end get the scale's end colors even if their widths differ.
+    fn class_color(&self, index: usize, count: usize) -> Color {
+        let Domain::Numeric { min, max, .. } = &self.domain else {
+            return self.no_data_color;
+        };
+        let t = if count > 1 {
+            index as f64 / (count - 1) as f64
+        } else {
+            0.5
+        };
+        self.scale.color((min + (max - min) * t) as f32)
+    }
+
+    pub fn color(&self, value: Option<&str>) -> Color {
+        let Some(value) = value.map(str::trim) else {
+            return self.no_data_color;
+        };
+        match &self.domain {
+            Domain::Categories(categories) => {
+                match categories.iter().position(|category| category == value) {
+                    Some(index) => self.scale.categorical(index),
+                    None => self.no_data_color,
+                }
+            }
+            Domain::Numeric { breaks, .. } => {
+                let Some(value) = value.parse::<f64>().ok().filter(|value| value.is_finite())
+                else {
+                    return self.no_data_color;
+                };
+                if breaks.is_empty() {
+                    return self.scale.color(value as f32);
+                }
+                let class = breaks.iter().take_while(|limit| value >= **limit).count();
+                self.class_color(class, breaks.len() + 1)
+            }
+        }
+    }
+
+    /// Legend rows describing this scale, from the smallest values up
+    pub fn legend(&self) -> Vec<LegendEntry> {
+        match &self.domain {
+            Domain::Categories(categories) => categories
+                .iter()
+                .map(|category| LegendEntry {
+                    color: self.color(Some(category)),
+                    label: category.clone(),
+                })
+                .collect(),
+            Domain::Numeric { min, max, breaks } => {
+                if breaks.is_empty() {
+                    (0..CONTINUOUS_LEGEND_STOPS)
+                        .map(|i| {
+                            let value =
+                                min + (max - min) * i as f64 / (CONTINUOUS_LEGEND_STOPS - 1) as f64;
+                            LegendEntry {
+                                color: self.scale.color(value as f32),
+                                label: format_value(value),
+                            }
+                        })
+                        .collect()
+                } else {
+                    let classes = self.classes();
+                    let count = classes.len();
+                    classes
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, class)| LegendEntry {
+                            color: self.class_color(index, count),
+                            label: format!(
+                                "{} \u{2013} {}",
+                                format_value(class.0),
+                                format_value(class.1)
+                            ),
+                        })
+                        .collect()
+                }
+            }
+        }
+    }
+}
+
+/// UI node listing the legend of a layer's [`Choropleth`]
+#[derive(Component)]
+pub struct ChoroplethLegend {
+    pub layer: Entity,
+}
+
+/// System to recolor polygon fills when a layer's [`Choropleth`] changes or its features are
+/// spawned, and to rebuild its legend.
+///
+/// Fills keep the alpha of the layer's fill color.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_choropleths(
+    mut commands: Commands,
+    layers: Query<(Entity, &PolygonLayer, Ref<Choropleth>)>,
+    features: Query<(&GeoFeature, &Handle<StandardMaterial>)>,
+    new_features: Query<&GeoFeature, Added<GeoFeature>>,
+    legends: Query<(Entity, &ChoroplethLegend)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    label_settings: Res<LabelSettings>,
+    asset_server: Res<AssetServer>,
+) {
+    let spawned: HashSet<Entity> = new_features.iter().map(|feature| feature.layer).collect();
+    for (entity, layer, choropleth) in layers.iter() {
+        if !choropleth.is_changed() && !spawned.contains(&entity) {
+            continue;
+        }
+        let layer_features = || {
+            features
+                .iter()
+                .filter(move |(feature, _)| feature.layer == entity)
+        };
+        let scale = choropleth.fit(layer_features().map(|(feature, _)| {
+            feature
+                .properties
+                .get(&choropleth.property)
+                .map(String::as_str)
+        }));
+        for (feature, material) in layer_features() {
+            let value = feature.properties.get(&choropleth.property);
+            if let Some(material) = materials.get_mut(material) {
+                material.base_color = scale
+                    .color(value.map(String::as_str))
+                    .with_a(layer.fill_color.a());
+            }
+        }
+
+        for (legend, _) in legends.iter().filter(|(_, legend)| legend.layer == entity) {
+            commands.entity(legend).despawn_recursive();
+        }
+        if let Some(title) = &choropleth.legend_title {
+            spawn_legend(
+                &mut commands,
+                entity,
+                title,
+                &scale.legend(),
+                asset_server.load(label_settings.font_path.as_str()),
+                &label_settings,
+            );
+        }
+    }
+}
+
+fn spawn_legend(
+    commands: &mut Commands,
+    layer: Entity,
+    title: &str,
+    entries: &[LegendEntry],
+    font: Handle<Font>,
+    settings: &LabelSettings,
+) {
+    let text_style = TextStyle {
+        font,
+        font_size: settings.font_size,
+        color: settings.color,
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Px(10.0),
+                        bottom: Val::Px(10.0),
+                        ..default()
+                    },
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(8.0)),
+                    gap: Size::height(Val::Px(4.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+            ChoroplethLegend { layer },
+        ))
+        .with_children(|legend| {
+            legend.spawn(TextBundle::from_section(title, text_style.clone()));
+            for entry in entries {
+                legend
+                    .spawn(NodeBundle {
+                        style: Style {
+                            align_items: AlignItems::Center,
+                            gap: Size::width(Val::Px(6.0)),
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|row| {
+                        row.spawn(NodeBundle {
+                            style: Style {
+                                size: Size::all(Val::Px(settings.font_size)),
+                                ..default()
+                            },
+                            background_color: entry.color.into(),
+                            ..default()
+                        });
+                        row.spawn(TextBundle::from_section(&entry.label, text_style.clone()));
+                    });
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classification_breaks() {
+        assert_eq!(equal_interval_breaks(0.0, 100.0, 4), vec![25.0, 50.0, 75.0]);
+        let sorted = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 100.0];
+        assert_eq!(quantile_breaks(&sorted, 2), vec![6.0]);
+        assert_eq!(quantile_breaks(&[1.0, 1.0, 1.0, 2.0], 4), vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_choropleth_colors_and_legend() {
+        let choropleth = Choropleth::new("pop")
+            .with_scale(ColorScale::from_colors(vec![Color::BLACK, Color::WHITE]))
+            .with_classification(Classification::EqualInterval(2));
+        let scale = choropleth.fit([Some("0"), Some("10"), None, Some("n/a")]);
+        assert_eq!(scale.color(Some("2")), Color::BLACK);
+        assert_eq!(scale.color(Some("10")), Color::WHITE);
+        assert_eq!(scale.color(Some("n/a")), Color::GRAY);
+        assert_eq!(scale.color(None), Color::GRAY);
+        let legend = scale.legend();
+        assert_eq!(legend.len(), 2);
+        assert_eq!(legend[0].label, "0 \u{2013} 5");
+        assert_eq!(legend[1].color, scale.color(Some("7")));
+
+        let categorical = Choropleth::new("continent")
+            .with_scale(ColorScale::from_colors(vec![Color::RED, Color::GREEN]))
+            .with_classification(Classification::Categorical);
+        let scale = categorical.fit([Some("Europe"), Some("Asia"), Some("Africa")]);
+        assert_eq!(scale.color(Some("Africa")), Color::RED);
+        assert_eq!(scale.color(Some("Asia")), Color::GREEN);
+        assert_eq!(scale.color(Some("Europe")), Color::RED);
+        assert_eq!(scale.legend().len(), 3);
+    }
+}
@@ -0,0 +1,330 @@
+use std::cmp::Ordering;
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::labels::WorldLabel;
+use crate::map::EARTH_RADIUS;
+use crate::point_cloud::{PointCloud, PointInstance, PointShape};
+use crate::points::{PointLabel, PointLayer, PointMarkers};
+
+/// Seconds taken to merge or expand clusters after the zoom crosses a clustering level
+const TRANSITION_SECONDS: f32 = 0.35;
+
+/// Clustering levels per doubling of the cluster angle. Fewer levels recluster less often.
+const LEVELS_PER_OCTAVE: f32 = 2.0;
+
+/// Clusters never grow beyond this multiple of the layer's largest marker, or tallest column
+const MAX_CLUSTER_SCALE: f32 = 2.0;
+
+/// A group of points shown as a single marker at the representative's position
+#[derive(Debug, PartialEq)]
+pub struct Cluster {
+    /// The highest priority member
+    pub representative: usize,
+    /// All members, including the representative
+    pub members: Vec<usize>,
+}
+
+/// Greedily groups unit directions that lie within `max_angle` radians of a higher priority
+/// point, visiting points from highest priority down.
+///
+/// Points are bucketed in a grid with cells as wide as the matching chord length, so each
+/// point only has to be compared against the 27 cells around it.
+pub fn cluster_directions(directions: &[Vec3], priorities: &[f32], max_angle: f32) -> Vec<Cluster> {
+    let mut order: Vec<usize> = (0..directions.len()).collect();
+    order.sort_by(|&a, &b| {
+        priorities[b]
+            .partial_cmp(&priorities[a])
+            .unwrap_or(Ordering::Equal)
+    });
+    if max_angle <= 0.0 {
+        return order
+            .into_iter()
+            .map(|index| Cluster {
+                representative: index,
+                members: vec![index],
+            })
+            .collect();
+    }
+
+    // Straight-line distance between two unit vectors `max_angle` apart
+    let chord = 2.0 * (max_angle.min(PI) / 2.0).sin();
+    let cell = |direction: Vec3| (direction / chord).floor().as_ivec3();
+    let mut grid: HashMap<IVec3, Vec<usize>> = HashMap::default();
+    for (index, direction) in directions.iter().enumerate() {
+        grid.entry(cell(*direction)).or_default().push(index);
+    }
+
+    let mut assigned = vec![false; directions.len()];
+    let mut clusters = Vec::new();
+    for representative in order {
+        if assigned[representative] {
+            continue;
+        }
+        assigned[representative] = true;
+        let center = directions[representative];
+        let center_cell = cell(center);
+        let mut members = vec![representative];
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let Some(indices) = grid.get(&(center_cell + IVec3::new(x, y, z))) else {
+                        continue;
+                    };
+                    for &index in indices {
+                        if !assigned[index] && directions[index].distance(center) <= chord {
+                            assigned[index] = true;
+                            members.push(index);
+                        }
+                    }
+                }
+            }
+        }
+        clusters.push(Cluster {
+            representative,
+            members,
+        });
+    }
+    clusters
+}
+
+fn cluster_level(angle: f32) -> i32 {
+    (angle.log2() * LEVELS_PER_OCTAVE).round() as i32
+}
+
+fn level_angle(level: i32) -> f32 {
+    2f32.powf(level as f32 / LEVELS_PER_OCTAVE)
+}
+
+/// Instances with every cluster drawn at its representative. Spheres get the combined area of
+/// their members and columns the combined height, both capped at `max_size`. The other
+/// members shrink to nothing at the same spot, so they can grow back out when the cluster
+/// expands.
+fn clustered_instances(
+    base: &[PointInstance],
+    clusters: &[Cluster],
+    shape: PointShape,
+    max_size: f32,
+) -> Vec<PointInstance> {
+    let mut instances = base.to_vec();
+    for cluster in clusters {
+        let representative = base[cluster.representative];
+        let merged = match shape {
+            PointShape::Sphere => {
+                let area: f32 = cluster
+                    .members
+                    .iter()
+                    .map(|&index| base[index].scale * base[index].scale)
+                    .sum();
+                let scale = area.sqrt().min(max_size);
+                PointInstance {
+                    scale,
+                    height: scale,
+                    ..representative
+                }
+            }
+            PointShape::Column => {
+                let height: f32 = cluster
+                    .members
+                    .iter()
+                    .map(|&index| base[index].height * 2.0)
+                    .sum();
+                let height = height.min(max_size);
+                let ground = representative.base();
+                PointInstance {
+                    position: ground + ground.normalize_or_zero() * height / 2.0,
+                    height: height / 2.0,
+                    ..representative
+                }
+            }
+        };
+        for &index in &cluster.members {
+            instances[index].position = merged.position;
+            instances[index].scale = 0.0;
+            instances[index].height = 0.0;
+        }
+        instances[cluster.representative] = merged;
+    }
+    instances
+}
+
+/// Clustering of a [`PointCloud`] spawned by a layer with a cluster radius
+#[derive(Component)]
+pub struct ClusterState {
+    base: Vec<PointInstance>,
+    level: Option<i32>,
+    from: Vec<PointInstance>,
+    to: Vec<PointInstance>,
+    elapsed: f32,
+}
+
+impl ClusterState {
+    fn new(base: Vec<PointInstance>) -> Self {
+        Self {
+            base,
+            level: None,
+            from: Vec::new(),
+            to: Vec::new(),
+            elapsed: TRANSITION_SECONDS,
+        }
+    }
+}
+
+/// System to merge the markers of clustered point layers that are too close together on
+/// screen, and to animate clusters splitting and merging as the camera zooms.
+///
+/// The cluster radius is converted to an angle on the globe from the camera's altitude and
+/// field of view. Labels of merged markers are hidden, and the representative's label shows
+/// how many markers it stands for.
+pub fn cluster_point_layers(
+    mut commands: Commands,
+    time: Res<Time>,
+    cameras: Query<(&Camera, &Projection, &GlobalTransform), With<Camera3d>>,
+    layers: Query<&PointLayer>,
+    mut clouds: Query<(
+        Entity,
+        &Parent,
+        &PointMarkers,
+        &mut PointCloud,
+        Option<&mut ClusterState>,
+    )>,
+    mut labels: Query<(&mut WorldLabel, &PointLabel, &mut Text)>,
+) {
+    let Some((camera, Projection::Perspective(projection), camera_transform)) =
+        cameras.iter().find(|(camera, ..)| camera.is_active)
+    else {
+        return;
+    };
+    let Some(viewport) = camera.logical_viewport_size() else {
+        return;
+    };
+    let altitude = (camera_transform.translation().length() - EARTH_RADIUS).max(0.0);
+    let angle_per_pixel = altitude * (projection.fov / viewport.y) / EARTH_RADIUS;
+
+    for (entity, parent, point_markers, mut cloud, state) in clouds.iter_mut() {
+        let Ok(layer) = layers.get(parent.get()) else {
+            continue;
+        };
+        let Some(radius) = layer.cluster_radius else {
+            continue;
+        };
+        let Some(mut state) = state else {
+            commands
+                .entity(entity)
+                .insert(ClusterState::new(cloud.instances.clone()));
+            continue;
+        };
+
+        let level = cluster_level(radius * angle_per_pixel);
+        if state.level != Some(level) {
+            let directions: Vec<Vec3> = state
+                .base
+                .iter()
+                .map(|instance| instance.position.normalize_or_zero())
+                .collect();
+            let values: Vec<f32> = point_markers
+                .markers
+                .iter()
+                .map(|marker| marker.value.unwrap_or(0.0))
+                .collect();
+            let clusters = cluster_directions(&directions, &values, level_angle(level));
+            state.from = cloud.instances.clone();
+            state.to = clustered_instances(
+                &state.base,
+                &clusters,
+                cloud.shape,
+                layer.size_scale.max_size() * MAX_CLUSTER_SCALE,
+            );
+            state.level = Some(level);
+            state.elapsed = 0.0;
+
+            let mut membership = HashMap::default();
+            for cluster in &clusters {
+                let total: f32 = cluster.members.iter().map(|&index| values[index]).sum();
+                for &index in &cluster.members {
+                    let shown = (index == cluster.representative).then_some(cluster.members.len());
+                    membership.insert(index, (shown, total));
+                }
+            }
+            for (mut label, point_label, mut text) in labels.iter_mut() {
+                if label.owner != entity {
+                    continue;
+                }
+                let Some((shown, total)) = membership.get(&point_label.index) else {
+                    continue;
+                };
+                let name = point_markers.markers[point_label.index]
+                    .label
+                    .as_deref()
+                    .unwrap_or_default();
+                label.hidden = shown.is_none();
+                label.priority = *total;
+                let value = match shown {
+                    Some(count) if *count > 1 => format!("{name} +{}", count - 1),
+                    _ => name.to_string(),
+                };
+                if let Some(section) = text.sections.first_mut() {
+                    section.value = value;
+                }
+            }
+        }
+
+        if state.elapsed < TRANSITION_SECONDS {
+            state.elapsed = (state.elapsed + time.delta_seconds()).min(TRANSITION_SECONDS);
+            let t = state.elapsed / TRANSITION_SECONDS;
+            let t = t * t * (3.0 - 2.0 * t);
+            cloud.instances = state
+                .from
+                .iter()
+                .zip(&state.to)
+                .map(|(from, to)| PointInstance {
+                    position: from.position.lerp(to.position, t),
+                    scale: from.scale + (to.scale - from.scale) * t,
+                    color: to.color,
+                    height: from.height + (to.height - from.height) * t,
+                })
+                .collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Coordinates;
+
+    fn direction(latitude: f32, longitude: f32) -> Vec3 {
+        Coordinates::from_degrees(latitude, longitude)
+            .unwrap()
+            .get_point_on_sphere()
+            .normalize()
+    }
+
+    #[test]
+    fn test_clusters_by_angle_and_priority() {
+        let directions = [
+            direction(35.7, 139.7), // Tokyo
+            direction(34.7, 135.5), // Osaka, about 3.6 degrees away
+            direction(40.7, -74.0), // New York
+            direction(35.7, 139.8),
+        ];
+        let priorities = [37.4, 19.1, 18.8, 0.0];
+
+        let clusters = cluster_directions(&directions, &priorities, 1f32.to_radians());
+        assert_eq!(clusters.len(), 3);
+        assert_eq!(clusters[0].representative, 0);
+        assert_eq!(clusters[0].members, vec![0, 3]);
+
+        let clusters = cluster_directions(&directions, &priorities, 5f32.to_radians());
+        assert_eq!(clusters.len(), 2);
+        let mut members = clusters[0].members.clone();
+        members.sort();
+        assert_eq!(members, vec![0, 1, 3]);
+        assert_eq!(clusters[1].members, vec![2]);
+
+        assert_eq!(cluster_directions(&directions, &priorities, 0.0).len(), 4);
+        assert_eq!(cluster_directions(&directions, &priorities, PI).len(), 1);
+    }
+}
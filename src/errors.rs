@@ -15,3 +15,21 @@ impl fmt::Debug for CoordError {
         write!(f, "CoordError: {}", self.msg)
     }
 }
+
+pub struct PointDataError {
+    pub msg: String,
+}
+
+impl fmt::Display for PointDataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl fmt::Debug for PointDataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PointDataError: {}", self.msg)
+    }
+}
+
+impl std::error::Error for PointDataError {}
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+
+use crate::map::{generate_face, Coordinates, HeightSource, PATCH_OFFSETS};
+use crate::tiles::CUBE_FACES;
+
+/// Vertices closer than this, in world units, are treated as the same point when welding
+const WELD_TOLERANCE: f32 = 1e-3;
+
+/// Latitude/longitude box, in degrees, used to export part of the globe.
+///
+/// A `min_longitude` greater than `max_longitude` describes a box crossing the antimeridian.
+#[derive(Debug, Clone, Copy)]
+pub struct ExportRegion {
+    pub min_latitude: f32,
+    pub min_longitude: f32,
+    pub max_latitude: f32,
+    pub max_longitude: f32,
+}
+
+impl ExportRegion {
+    pub fn contains(&self, point: Vec3) -> bool {
+        let (lat, lon) = Coordinates::from(point).as_degrees();
+        let in_longitude = if self.min_longitude <= self.max_longitude {
+            (self.min_longitude..=self.max_longitude).contains(&lon)
+        } else {
+            lon >= self.min_longitude || lon <= self.max_longitude
+        };
+        in_longitude && (self.min_latitude..=self.max_latitude).contains(&lat)
+    }
+}
+
+/// Builds all 24 terrain patches, as `generate_faces` does but without spawning them
+pub fn generate_globe(
+    resolution: u32,
+    exaggeration: f64,
+    rs: &(impl HeightSource + ?Sized),
+) -> Vec<Mesh> {
+    let mut meshes = Vec::new();
+    for direction in CUBE_FACES {
+        for offset in &PATCH_OFFSETS {
+            meshes.push(generate_face(
+                direction,
+                resolution,
+                offset.0,
+                offset.1,
+                exaggeration,
+                rs,
+            ));
+        }
+    }
+    meshes
+}
+
+/// Terrain patches merged into one indexed triangle mesh, ready to be written to disk.
+///
+/// Duplicate vertices along patch borders are welded, and normals are recomputed from the
+/// displaced surface so exported relief is shaded correctly. Vertices that only differ by UV
+/// share exactly the same position.
+pub struct TerrainExport {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+impl TerrainExport {
+    /// Merges patch meshes, keeping only triangles entirely inside `region` if one is given
+    pub fn from_meshes(meshes: &[Mesh], region: Option<&ExportRegion>) -> Self {
+        let mut export = Self {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+        };
+        let mut points = PointWelder::default();
+        let mut welded: HashMap<(usize, [i32; 2]), u32> = HashMap::new();
+
+        for mesh in meshes {
+            let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+                Some(VertexAttributeValues::Float32x3(positions)) => positions,
+                _ => continue,
+            };
+            let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+                Some(VertexAttributeValues::Float32x2(uvs)) => uvs,
+                _ => continue,
+            };
+            let Some(indices) = mesh.indices() else {
+                continue;
+            };
+            let indices: Vec<usize> = indices.iter().collect();
+
+            for triangle in indices.chunks_exact(3) {
+                if let Some(region) = region {
+                    if !triangle
+                        .iter()
+                        .all(|i| region.contains(Vec3::from(positions[*i])))
+                    {
+                        continue;
+                    }
+                }
+                let corners = triangle.iter().map(|i| {
+                    let (point, position) = points.weld(positions[*i]);
+                    let key = (point, quantize_uv(uvs[*i]));
+                    *welded.entry(key).or_insert_with(|| {
+                        export.positions.push(position);
+                        export.uvs.push(uvs[*i]);
+                        export.positions.len() as u32 - 1
+                    })
+                });
+                let corners: Vec<u32> = corners.collect();
+                // Welding can collapse slivers along the seams into degenerate triangles
+                if corners[0] != corners[1] && corners[1] != corners[2] && corners[0] != corners[2]
+                {
+                    export.indices.extend(corners);
+                }
+            }
+        }
+
+        export.normals = export.vertex_normals();
+        export
+    }
+
+    /// Area-weighted vertex normals, shared across vertices that only differ by UV
+    fn vertex_normals(&self) -> Vec<[f32; 3]> {
+        let mut by_position: HashMap<[i32; 3], Vec3> = HashMap::new();
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(self.positions[triangle[i] as usize]));
+            let face_normal = (b - a).cross(c - a);
+            for i in triangle {
+                *by_position
+                    .entry(quantize(self.positions[*i as usize]))
+                    .or_default() += face_normal;
+            }
+        }
+        self.positions
+            .iter()
+            .map(|position| {
+                let normal = by_position
+                    .get(&quantize(*position))
+                    .copied()
+                    .unwrap_or_default();
+                normal
+                    .try_normalize()
+                    .unwrap_or_else(|| Vec3::from(*position).normalize())
+                    .to_array()
+            })
+            .collect()
+    }
+
+    pub fn write_obj(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "# Exported by bevy-earth")?;
+        for [x, y, z] in &self.positions {
+            writeln!(writer, "v {x} {y} {z}")?;
+        }
+        for [u, v] in &self.uvs {
+            // OBJ texture coordinates start at the bottom left
+            writeln!(writer, "vt {u} {}", 1.0 - v)?;
+        }
+        for [x, y, z] in &self.normals {
+            writeln!(writer, "vn {x} {y} {z}")?;
+        }
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+            writeln!(writer, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+        Ok(())
+    }
+
+    /// Writes binary STL.
+    ///
+    /// Patch borders are welded to shared positions, so a whole-globe export is watertight and
+    /// can be sliced for 3D printing.
+    pub fn write_stl(&self, mut writer: impl Write) -> io::Result<()> {
+        let positions: Vec<Vec3> = self.positions.iter().copied().map(Vec3::from).collect();
+
+        let mut header = [0u8; 80];
+        let title = b"bevy-earth terrain";
+        header[..title.len()].copy_from_slice(title);
+        writer.write_all(&header)?;
+        writer.write_all(&(self.indices.len() as u32 / 3).to_le_bytes())?;
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| positions[triangle[i] as usize]);
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+            for vector in [normal, a, b, c] {
+                for value in vector.to_array() {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+            writer.write_all(&0u16.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Writes a glTF 2.0 file with its geometry in a `.bin` buffer alongside it.
+    ///
+    /// `texture_uri` is written as-is into the file, so it should be relative to the `.gltf`
+    /// (e.g. a copy of `earth_color_10K.png` placed next to it).
+    pub fn write_gltf(&self, path: impl AsRef<Path>, texture_uri: Option<&str>) -> io::Result<()> {
+        let path = path.as_ref();
+        let bin_path = path.with_extension("bin");
+        let bin_name = bin_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let mut bin = BufWriter::new(File::create(&bin_path)?);
+        for value in self.positions.iter().chain(&self.normals).flatten() {
+            bin.write_all(&value.to_le_bytes())?;
+        }
+        for value in self.uvs.iter().flatten() {
+            bin.write_all(&value.to_le_bytes())?;
+        }
+        for index in &self.indices {
+            bin.write_all(&index.to_le_bytes())?;
+        }
+        bin.flush()?;
+
+        let vertex_count = self.positions.len();
+        let vec3_bytes = vertex_count * 12;
+        let uv_bytes = vertex_count * 8;
+        let index_bytes = self.indices.len() * 4;
+        let (min, max) = self.positions.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), position| {
+                (
+                    min.min(Vec3::from(*position)),
+                    max.max(Vec3::from(*position)),
+                )
+            },
+        );
+
+        let material = match texture_uri {
+            Some(_) => {
+                r#"{"pbrMetallicRoughness":{"baseColorTexture":{"index":0},"metallicFactor":0.0,"roughnessFactor":1.0}}"#
+            }
+            None => r#"{"pbrMetallicRoughness":{"metallicFactor":0.0,"roughnessFactor":1.0}}"#,
+        };
+        let texture = match texture_uri {
+            Some(uri) => format!(
+                r#","images":[{{"uri":"{}"}}],"samplers":[{{"magFilter":9729,"minFilter":9987}}],"textures":[{{"source":0,"sampler":0}}]"#,
+                json_escape(uri)
+            ),
+            None => String::new(),
+        };
+
+        let json = format!(
+            r#"{{"asset":{{"version":"2.0","generator":"bevy-earth"}},"scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0,"name":"Earth"}}],"meshes":[{{"primitives":[{{"attributes":{{"POSITION":0,"NORMAL":1,"TEXCOORD_0":2}},"indices":3,"material":0}}]}}],"materials":[{material}]{texture},"buffers":[{{"uri":"{bin_uri}","byteLength":{buffer_length}}}],"bufferViews":[{{"buffer":0,"byteOffset":0,"byteLength":{vec3_bytes},"target":34962}},{{"buffer":0,"byteOffset":{vec3_bytes},"byteLength":{vec3_bytes},"target":34962}},{{"buffer":0,"byteOffset":{uv_offset},"byteLength":{uv_bytes},"target":34962}},{{"buffer":0,"byteOffset":{index_offset},"byteLength":{index_bytes},"target":34963}}],"accessors":[{{"bufferView":0,"componentType":5126,"count":{vertex_count},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}},{{"bufferView":1,"componentType":5126,"count":{vertex_count},"type":"VEC3"}},{{"bufferView":2,"componentType":5126,"count":{vertex_count},"type":"VEC2"}},{{"bufferView":3,"componentType":5125,"count":{index_count},"type":"SCALAR"}}]}}"#,
+            min.x,
+            min.y,
+            min.z,
+            max.x,
+            max.y,
+            max.z,
+            bin_uri = json_escape(&bin_name),
+            buffer_length = vec3_bytes * 2 + uv_bytes + index_bytes,
+            uv_offset = vec3_bytes * 2,
+            index_offset = vec3_bytes * 2 + uv_bytes,
+            index_count = self.indices.len(),
+        );
+        std::fs::write(path, json)
+    }
+}
+
+fn quantize(position: [f32; 3]) -> [i32; 3] {
+    position.map(|value| (value / WELD_TOLERANCE).round() as i32)
+}
+
+/// Merges points within [`WELD_TOLERANCE`] of each other.
+///
+/// Points are bucketed by [`quantize`], and each lookup searches the neighbouring buckets too,
+/// as the same border vertex computed by two patches can round into different ones.
+#[derive(Default)]
+struct PointWelder {
+    points: Vec<[f32; 3]>,
+    buckets: HashMap<[i32; 3], Vec<usize>>,
+}
+
+impl PointWelder {
+    /// Index and position of the point `position` is welded to, adding it if it's new
+    fn weld(&mut self, position: [f32; 3]) -> (usize, [f32; 3]) {
+        let bucket = quantize(position);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let neighbor = [bucket[0] + dx, bucket[1] + dy, bucket[2] + dz];
+                    let Some(candidates) = self.buckets.get(&neighbor) else {
+                        continue;
+                    };
+                    for &index in candidates {
+                        let point = self.points[index];
+                        if Vec3::from(point).distance(Vec3::from(position)) <= WELD_TOLERANCE {
+                            return (index, point);
+                        }
+                    }
+                }
+            }
+        }
+        self.points.push(position);
+        self.buckets
+            .entry(bucket)
+            .or_default()
+            .push(self.points.len() - 1);
+        (self.points.len() - 1, position)
+    }
+}
+
+fn quantize_uv(uv: [f32; 2]) -> [i32; 2] {
+    uv.map(|value| (value * 1e6).round() as i32)
+}
+
+fn json_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::FlatSurface;
+
+    /// Rolling terrain, so patch borders are displaced like real elevation data
+    struct Hills;
+
+    impl HeightSource for Hills {
+        fn height_at(&self, latitude: f64, longitude: f64) -> Option<f64> {
+            Some(3000.0 + 2000.0 * (latitude * 0.3).sin() * (longitude * 0.2).cos())
+        }
+    }
+
+    #[test]
+    fn test_whole_globe_is_watertight() {
+        for (resolution, exaggeration, rs) in [
+            (6, 1.0, &FlatSurface as &dyn HeightSource),
+            (100, 1.0 / 300.0, &Hills),
+        ] {
+            let meshes = generate_globe(resolution, exaggeration, rs);
+            let export = TerrainExport::from_meshes(&meshes, None);
+
+            // Every edge of a closed surface is shared by exactly two triangles. Welded
+            // vertices share their exact position, so positions identify them.
+            let mut edges: HashMap<([u32; 3], [u32; 3]), u32> = HashMap::new();
+            for triangle in export.indices.chunks_exact(3) {
+                for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+                    let a = export.positions[triangle[a] as usize].map(f32::to_bits);
+                    let b = export.positions[triangle[b] as usize].map(f32::to_bits);
+                    *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+                }
+            }
+            assert!(edges.values().all(|count| *count == 2));
+        }
+    }
+
+    #[test]
+    fn test_region_keeps_only_contained_triangles() {
+        let region = ExportRegion {
+            min_latitude: -30.0,
+            min_longitude: 60.0,
+            max_latitude: 30.0,
+            max_longitude: 120.0,
+        };
+        let export =
+            TerrainExport::from_meshes(&generate_globe(12, 1.0, &FlatSurface), Some(&region));
+        assert!(!export.indices.is_empty());
+        assert!(export
+            .positions
+            .iter()
+            .all(|position| region.contains(Vec3::from(*position))));
+    }
+}
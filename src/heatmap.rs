@@ -0,0 +1,308 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+use crate::map::EARTH_RADIUS_KM;
+use crate::overlay::{equirectangular_image, Overlay};
+use crate::points::{read_markers, PointTable};
+use crate::scale::{ColorScale, Palette};
+
+/// The kernel is cut off at this many bandwidths, where it has fallen to about 1%
+const KERNEL_CUTOFF: f32 = 3.0;
+
+/// Densities below this fraction of the peak fade to transparent, so the heatmap has soft
+/// edges rather than tinting the whole globe
+const FADE_IN: f32 = 0.1;
+
+/// Latitude and longitude in radians of the center of a pixel of an equirectangular image
+fn pixel_coordinates(x: usize, y: usize, width: usize, height: usize) -> (f32, f32) {
+    let latitude = PI / 2.0 - (y as f32 + 0.5) * PI / height as f32;
+    let longitude = -PI + (x as f32 + 0.5) * 2.0 * PI / width as f32;
+    (latitude, longitude)
+}
+
+/// Kernel density of weighted points on the unit sphere, sampled at the pixel centers of a
+/// `width` by `height` equirectangular grid, row by row from the north pole.
+///
+/// Each point spreads its weight with a Gaussian kernel of its great-circle distance, with a
+/// standard deviation of `bandwidth` radians. Densities are sums of kernel values rather than
+/// normalized probabilities.
+pub fn spherical_kde(
+    points: &[(f32, f32)],
+    weights: &[f32],
+    bandwidth: f32,
+    width: usize,
+    height: usize,
+) -> Vec<f32> {
+    let mut density = vec![0.0; width * height];
+    if width == 0 || height == 0 || bandwidth <= 0.0 {
+        return density;
+    }
+    let cutoff = (KERNEL_CUTOFF * bandwidth).min(PI);
+    let row_height = PI / height as f32;
+    let column_width = 2.0 * PI / width as f32;
+    let rows: Vec<(f32, f32)> = (0..height)
+        .map(|y| pixel_coordinates(0, y, width, height).0.sin_cos())
+        .collect();
+
+    for (&(latitude, longitude), &weight) in points.iter().zip(weights) {
+        let (sin_latitude, cos_latitude) = latitude.sin_cos();
+        let first_row = ((PI / 2.0 - latitude - cutoff) / row_height)
+            .floor()
+            .max(0.0) as usize;
+        let last_row = (((PI / 2.0 - latitude + cutoff) / row_height).ceil() as usize).min(height);
+        for (y, &(sin_row, cos_row)) in rows.iter().enumerate().take(last_row).skip(first_row) {
+            // Longitudes within the cutoff of the point on this row, or all of them near a pole
+            let columns = if cutoff.sin() < cos_row && cutoff < PI / 2.0 {
+                let half_width = (cutoff.sin() / cos_row).asin();
+                let first = ((longitude - half_width + PI) / column_width).floor() as isize;
+                let last = ((longitude + half_width + PI) / column_width).ceil() as isize;
+                (first, last.min(first + width as isize))
+            } else {
+                (0, width as isize)
+            };
+            for column in columns.0..columns.1 {
+                let x = column.rem_euclid(width as isize) as usize;
+                let (_, pixel_longitude) = pixel_coordinates(x, y, width, height);
+                let cos_distance = sin_latitude * sin_row
+                    + cos_latitude * cos_row * (pixel_longitude - longitude).cos();
+                let distance = cos_distance.clamp(-1.0, 1.0).acos();
+                if distance <= cutoff {
+                    let z = distance / bandwidth;
+                    density[x + y * width] += weight * (-0.5 * z * z).exp();
+                }
+            }
+        }
+    }
+    density
+}
+
+/// Colors densities with a scale fitted from zero to the peak density, unless it has its own
+/// domain. Low densities fade out to transparent.
+pub fn heatmap_image(density: &[f32], width: usize, height: usize, scale: &ColorScale) -> Image {
+    let peak = density.iter().copied().fold(0.0, f32::max);
+    let scale = scale.fit([0.0, peak]);
+    // Pixels are colored in the same row-major order as the densities
+    let mut values = density.iter();
+    equirectangular_image(width, height, |_, _| {
+        let value = values.next().copied().unwrap_or(0.0);
+        let alpha = if peak > 0.0 {
+            (value / peak / FADE_IN).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let color = scale.color(value);
+        color.with_a(color.a() * alpha)
+    })
+}
+
+/// Draws the density of a table's points as a smooth heatmap over the terrain.
+///
+/// The heatmap is computed once the table has loaded, and again if the file or the layer
+/// changes. It is shown through an [`Overlay`] on the same entity, whose opacity can be
+/// changed afterwards. Changing only the layer's opacity updates the overlay without computing
+/// the heatmap again.
+#[derive(Component, Clone)]
+pub struct HeatmapLayer {
+    pub source: Handle<PointTable>,
+    pub latitude_column: String,
+    pub longitude_column: String,
+    /// Weights each point by this column, or counts every point once if `None`
+    pub weight_column: Option<String>,
+    /// Standard deviation of the kernel along the surface
+    pub bandwidth_km: f32,
+    /// Width of the heatmap image in pixels. It is half as tall.
+    pub resolution: usize,
+    pub color_scale: ColorScale,
+    pub opacity: f32,
+}
+
+impl HeatmapLayer {
+    pub fn new(source: Handle<PointTable>) -> Self {
+        Self {
+            source,
+            latitude_column: "latitude".to_string(),
+            longitude_column: "longitude".to_string(),
+            weight_column: None,
+            bandwidth_km: 250.0,
+            resolution: 1024,
+            color_scale: ColorScale::new(Palette::Magma),
+            opacity: 0.8,
+        }
+    }
+
+    pub fn with_position_columns(mut self, latitude: &str, longitude: &str) -> Self {
+        self.latitude_column = latitude.to_string();
+        self.longitude_column = longitude.to_string();
+        self
+    }
+
+    pub fn with_weight_column(mut self, column: &str) -> Self {
+        self.weight_column = Some(column.to_string());
+        self
+    }
+
+    pub fn with_bandwidth(mut self, kilometres: f32) -> Self {
+        self.bandwidth_km = kilometres;
+        self
+    }
+
+    pub fn with_resolution(mut self, width: usize) -> Self {
+        self.resolution = width;
+        self
+    }
+
+    pub fn with_color_scale(mut self, scale: ColorScale) -> Self {
+        self.color_scale = scale;
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
+    /// Whether both layers compute the same image, whatever their opacities
+    fn same_image(&self, other: &Self) -> bool {
+        self.source == other.source
+            && self.latitude_column == other.latitude_column
+            && self.longitude_column == other.longitude_column
+            && self.weight_column == other.weight_column
+            && self.bandwidth_km == other.bandwidth_km
+            && self.resolution == other.resolution
+            && self.color_scale == other.color_scale
+    }
+}
+
+/// Added to a [`HeatmapLayer`] entity once its data has been read, with the settings the
+/// heatmap was computed from
+#[derive(Component)]
+pub struct HeatmapComputed(HeatmapLayer);
+
+/// System to compute the image of heatmap layers whose data has loaded or changed
+pub fn update_heatmap_layers(
+    mut commands: Commands,
+    mut layers: Query<(Entity, Ref<HeatmapLayer>, Option<&mut Overlay>)>,
+    computed: Query<&HeatmapComputed>,
+    mut events: EventReader<AssetEvent<PointTable>>,
+    tables: Res<Assets<PointTable>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let modified: HashSet<Handle<PointTable>> = events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => Some(handle.clone()),
+            _ => None,
+        })
+        .collect();
+    for (entity, layer, overlay) in layers.iter_mut() {
+        let stale = modified.contains(&layer.source)
+            || !computed
+                .get(entity)
+                .is_ok_and(|computed| computed.0.same_image(&layer));
+        if !stale {
+            if let Some(mut overlay) = overlay.filter(|_| layer.is_changed()) {
+                overlay.opacity = layer.opacity;
+            }
+            continue;
+        }
+        let Some(table) = tables.get(&layer.source) else {
+            continue;
+        };
+        commands
+            .entity(entity)
+            .insert(HeatmapComputed(layer.clone()));
+        let markers = read_markers(
+            table,
+            &layer.latitude_column,
+            &layer.longitude_column,
+            layer.weight_column.as_deref(),
+            None,
+        );
+        let markers = match markers {
+            Ok(markers) => markers,
+            Err(err) => {
+                error!("Failed to read heatmap layer: {err}");
+                continue;
+            }
+        };
+        let points: Vec<(f32, f32)> = markers
+            .iter()
+            .map(|marker| (marker.coordinates.latitude, marker.coordinates.longitude))
+            .collect();
+        let weights: Vec<f32> = markers
+            .iter()
+            .map(|marker| match &layer.weight_column {
+                Some(_) => marker.value.unwrap_or(0.0),
+                None => 1.0,
+            })
+            .collect();
+
+        let width = layer.resolution.max(2);
+        let height = width / 2;
+        let density = spherical_kde(
+            &points,
+            &weights,
+            layer.bandwidth_km / EARTH_RADIUS_KM,
+            width,
+            height,
+        );
+        let image = heatmap_image(&density, width, height, &layer.color_scale);
+        match overlay {
+            Some(mut overlay) => {
+                overlay.image = images.add(image);
+                overlay.opacity = layer.opacity;
+            }
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(Overlay::new(images.add(image)).with_opacity(layer.opacity));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spherical_kde() {
+        let (width, height) = (72, 36);
+        let bandwidth = 10f32.to_radians();
+        let density = spherical_kde(&[(0.0, 0.0)], &[2.0], bandwidth, width, height);
+        let at = |latitude: f32, longitude: f32| {
+            let x = ((longitude + 180.0) / 5.0) as usize;
+            let y = ((90.0 - latitude) / 5.0) as usize;
+            density[x + y * width]
+        };
+
+        // Peaks next to the point, symmetric about it and cut off far away
+        let peak = density.iter().copied().fold(0.0, f32::max);
+        assert_eq!(at(2.5, 2.5), peak);
+        assert!((at(2.5, -2.5) - peak).abs() < 1e-5);
+        assert!((at(-2.5, 2.5) - peak).abs() < 1e-5);
+        assert!((peak - 2.0 * (-0.0625f32).exp()).abs() < 0.01);
+        assert_eq!(at(2.5, 42.5), 0.0);
+        assert_eq!(at(60.0, 2.5), 0.0);
+
+        // The kernel wraps across the antimeridian and covers the pole
+        let density = spherical_kde(
+            &[(0.0, PI), (PI / 2.0, 0.0)],
+            &[1.0, 1.0],
+            bandwidth,
+            width,
+            height,
+        );
+        assert!((density[17 * width] - density[(width - 1) + 17 * width]).abs() < 1e-5);
+        assert!(density[0] > 0.9 && (density[0] - density[width / 2]).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_opacity_keeps_image() {
+        let layer = HeatmapLayer::new(Handle::default());
+        assert!(layer.same_image(&layer.clone().with_opacity(0.3)));
+        assert!(!layer.same_image(&layer.clone().with_bandwidth(100.0)));
+    }
+}
@@ -0,0 +1,229 @@
+use std::cmp::Ordering;
+
+use bevy::math::Rect;
+use bevy::prelude::*;
+
+/// How world labels are drawn and decluttered
+#[derive(Resource)]
+pub struct LabelSettings {
+    /// Font asset path. Bevy has no built-in font, so this file has to be supplied.
+    pub font_path: String,
+    pub font_size: f32,
+    pub color: Color,
+    /// Pixels between a label's anchor and the bottom of its text
+    pub offset: f32,
+    /// Extra pixels kept clear around each label when decluttering
+    pub padding: f32,
+    /// Cosine of the angle from the horizon over which labels fade in. Labels at the horizon
+    /// are fully transparent and labels further in than this are opaque.
+    pub horizon_fade: f32,
+}
+
+impl Default for LabelSettings {
+    fn default() -> Self {
+        Self {
+            font_path: "fonts/FiraSans-Bold.ttf".to_string(),
+            font_size: 14.0,
+            color: Color::WHITE,
+            offset: 8.0,
+            padding: 2.0,
+            horizon_fade: 0.15,
+        }
+    }
+}
+
+/// Positions a UI text entity over a point on the globe.
+///
+/// When labels overlap on screen, the one with the highest `priority` is kept. The label is
+/// hidden while its `owner` is, e.g. when the owner's map layer is switched off, and despawned
+/// along with it.
+#[derive(Component, Debug)]
+pub struct WorldLabel {
+    pub anchor: Vec3,
+    pub priority: f32,
+    pub owner: Entity,
+    /// Keeps the label off screen regardless of decluttering, e.g. while its marker is merged
+    /// into a cluster
+    pub hidden: bool,
+}
+
+impl WorldLabel {
+    /// Text bundle for a label, positioned by [`update_world_labels`]
+    pub fn text_bundle(text: &str, font: Handle<Font>, settings: &LabelSettings) -> TextBundle {
+        TextBundle::from_section(
+            text,
+            TextStyle {
+                font,
+                font_size: settings.font_size,
+                color: settings.color,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            ..default()
+        })
+    }
+}
+
+/// Opacity of a label anchored on the globe's surface, fading out towards the horizon
+pub fn horizon_fade(anchor: Vec3, camera: Vec3, fade: f32) -> f32 {
+    let normal = anchor.normalize_or_zero();
+    let to_camera = (camera - anchor).normalize_or_zero();
+    (normal.dot(to_camera) / fade.max(f32::EPSILON)).clamp(0.0, 1.0)
+}
+
+/// A label competing for screen space
+#[derive(Clone, Copy, Debug)]
+pub struct LabelCandidate {
+    pub rect: Rect,
+    pub priority: f32,
+}
+
+/// Greedily keeps the highest priority labels that don't overlap any label already kept.
+///
+/// Returns whether each candidate is shown, in the order given. Equal priorities keep the
+/// earlier candidate, so the result is stable from frame to frame.
+pub fn declutter(candidates: &[LabelCandidate]) -> Vec<bool> {
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.sort_by(|&a, &b| {
+        candidates[b]
+            .priority
+            .partial_cmp(&candidates[a].priority)
+            .unwrap_or(Ordering::Equal)
+    });
+
+    let mut shown = vec![false; candidates.len()];
+    let mut placed: Vec<Rect> = Vec::new();
+    for index in order {
+        let rect = candidates[index].rect;
+        if placed.iter().all(|other| other.intersect(rect).is_empty()) {
+            placed.push(rect);
+            shown[index] = true;
+        }
+    }
+    shown
+}
+
+/// System to move world labels to their projected screen positions, fade them at the horizon
+/// and hide the lower priority label of any overlapping pair
+pub fn update_world_labels(
+    mut commands: Commands,
+    settings: Res<LabelSettings>,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut labels: Query<(
+        Entity,
+        &WorldLabel,
+        &Node,
+        &mut Style,
+        &mut Text,
+        &mut Visibility,
+    )>,
+    owners: Query<Option<&ComputedVisibility>>,
+) {
+    let Some((camera, camera_transform)) = cameras.iter().find(|(camera, _)| camera.is_active)
+    else {
+        return;
+    };
+    let camera_position = camera_transform.translation();
+
+    let mut candidates = Vec::new();
+    let mut placements = Vec::new();
+    for (entity, label, node, ..) in labels.iter() {
+        let Ok(owner_visibility) = owners.get(label.owner) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        // Labels are top-level UI nodes, so they don't inherit their owner's visibility
+        let owner_hidden =
+            owner_visibility.is_some_and(|visibility| !visibility.is_visible_in_hierarchy());
+        let fade = horizon_fade(label.anchor, camera_position, settings.horizon_fade);
+        let screen = camera.world_to_viewport(camera_transform, label.anchor);
+        let (Some(screen), true) = (screen, fade > 0.0 && !label.hidden && !owner_hidden) else {
+            placements.push((entity, None));
+            continue;
+        };
+        let size = node.size();
+        let rect = Rect::from_corners(
+            Vec2::new(screen.x - size.x / 2.0, screen.y + settings.offset),
+            Vec2::new(screen.x + size.x / 2.0, screen.y + settings.offset + size.y),
+        );
+        placements.push((entity, Some((rect.min, fade, candidates.len()))));
+        candidates.push(LabelCandidate {
+            rect: rect.inset(settings.padding),
+            priority: label.priority,
+        });
+    }
+
+    let shown = declutter(&candidates);
+    for (entity, placement) in placements {
+        let Ok((_, _, _, mut style, mut text, mut visibility)) = labels.get_mut(entity) else {
+            continue;
+        };
+        let placement = placement.filter(|(_, _, index)| shown[*index]);
+        let Some((min, fade, _)) = placement else {
+            if *visibility != Visibility::Hidden {
+                *visibility = Visibility::Hidden;
+            }
+            continue;
+        };
+        if *visibility != Visibility::Inherited {
+            *visibility = Visibility::Inherited;
+        }
+        // Viewport coordinates start at the bottom left of the window
+        let position = UiRect {
+            left: Val::Px(min.x),
+            bottom: Val::Px(min.y),
+            ..default()
+        };
+        if style.position != position {
+            style.position = position;
+        }
+        let color = settings
+            .color
+            .with_a(settings.color.a() * fade.clamp(0.0, 1.0));
+        for section in text.sections.iter_mut() {
+            if section.style.color != color {
+                section.style.color = color;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(x: f32, y: f32, priority: f32) -> LabelCandidate {
+        LabelCandidate {
+            rect: Rect::new(x, y, x + 40.0, y + 10.0),
+            priority,
+        }
+    }
+
+    #[test]
+    fn test_declutter_keeps_highest_priority() {
+        let candidates = [
+            candidate(0.0, 0.0, 1.0),
+            candidate(20.0, 5.0, 5.0),
+            candidate(50.0, 0.0, 2.0),
+            candidate(100.0, 100.0, 0.0),
+            candidate(100.0, 100.0, 0.0),
+        ];
+        assert_eq!(
+            declutter(&candidates),
+            vec![false, true, false, true, false]
+        );
+    }
+
+    #[test]
+    fn test_horizon_fade() {
+        let camera = Vec3::new(0.0, 0.0, 1000.0);
+        let facing = Vec3::new(0.0, 0.0, 300.0);
+        let behind = Vec3::new(0.0, 0.0, -300.0);
+        let limb = Vec3::new(0.0, 0.95, 0.31).normalize() * 300.0;
+        assert_eq!(horizon_fade(facing, camera, 0.15), 1.0);
+        assert_eq!(horizon_fade(behind, camera, 0.15), 0.0);
+        let fade = horizon_fade(limb, camera, 0.15);
+        assert!(fade > 0.0 && fade < 1.0);
+    }
+}
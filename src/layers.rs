@@ -0,0 +1,293 @@
+use bevy::asset::HandleId;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::map::EARTH_RADIUS;
+use crate::overlay::{Overlay, OverlayPatch};
+use crate::point_cloud::PointCloud;
+
+/// Name of the layer the terrain patches belong to
+pub const TERRAIN_LAYER: &str = "terrain";
+
+/// Depth bias added per step of z-order, so higher layers win where draped geometry overlaps
+const Z_ORDER_DEPTH_BIAS: f32 = 1.0;
+
+/// Runtime controls of a named layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerSettings {
+    pub visible: bool,
+    /// Multiplies the alpha of the layer's materials
+    pub opacity: f32,
+    /// Layers with a higher z-order are drawn over lower ones where they overlap
+    pub z_order: i32,
+    /// Camera altitudes above the surface, in world units, between which the layer is shown
+    pub min_altitude: f32,
+    pub max_altitude: f32,
+}
+
+impl Default for LayerSettings {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            opacity: 1.0,
+            z_order: 0,
+            min_altitude: 0.0,
+            max_altitude: f32::INFINITY,
+        }
+    }
+}
+
+impl LayerSettings {
+    /// Whether the layer is shown with the camera at `altitude`
+    pub fn shown_at(&self, altitude: f32) -> bool {
+        self.visible && (self.min_altitude..=self.max_altitude).contains(&altitude)
+    }
+}
+
+/// Registry of the named layers on the map, in the order they were registered.
+///
+/// Entities join a layer with a [`MapLayer`] component, which registers the layer with default
+/// settings if it doesn't exist yet. Layers can also be registered up front to configure them
+/// before anything is spawned.
+#[derive(Resource, Default)]
+pub struct Layers {
+    layers: Vec<(String, LayerSettings)>,
+}
+
+impl Layers {
+    /// Settings of a layer, registering it first if needed
+    pub fn register(&mut self, name: &str) -> &mut LayerSettings {
+        let index = match self.layers.iter().position(|(layer, _)| layer == name) {
+            Some(index) => index,
+            None => {
+                self.layers
+                    .push((name.to_string(), LayerSettings::default()));
+                self.layers.len() - 1
+            }
+        };
+        &mut self.layers[index].1
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LayerSettings> {
+        self.layers
+            .iter()
+            .find(|(layer, _)| layer == name)
+            .map(|(_, settings)| settings)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut LayerSettings> {
+        self.layers
+            .iter_mut()
+            .find(|(layer, _)| layer == name)
+            .map(|(_, settings)| settings)
+    }
+
+    /// Layers from the bottom of the z-order up, in registration order within the same z-order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &LayerSettings)> {
+        let mut layers: Vec<_> = self
+            .layers
+            .iter()
+            .map(|(name, settings)| (name.as_str(), settings))
+            .collect();
+        layers.sort_by_key(|(_, settings)| settings.z_order);
+        layers.into_iter()
+    }
+}
+
+/// Puts an entity and its descendants in a named layer of [`Layers`]
+#[derive(Component, Clone, Debug)]
+pub struct MapLayer {
+    pub name: String,
+}
+
+impl MapLayer {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+        }
+    }
+}
+
+/// Materials that a layer's opacity and z-order apply to, through [`apply_layer_materials`]
+pub trait LayerMaterial: Material {
+    /// Alpha of the material's color
+    fn alpha(&self) -> f32;
+    fn set_alpha(&mut self, alpha: f32);
+    fn set_alpha_mode(&mut self, alpha_mode: AlphaMode);
+    fn set_depth_bias(&mut self, depth_bias: f32);
+}
+
+impl LayerMaterial for StandardMaterial {
+    fn alpha(&self) -> f32 {
+        self.base_color.a()
+    }
+
+    fn set_alpha(&mut self, alpha: f32) {
+        self.base_color.set_a(alpha);
+    }
+
+    fn set_alpha_mode(&mut self, alpha_mode: AlphaMode) {
+        self.alpha_mode = alpha_mode;
+    }
+
+    fn set_depth_bias(&mut self, depth_bias: f32) {
+        self.depth_bias = depth_bias;
+    }
+}
+
+/// A material's own alpha, blending and depth bias, before a layer's settings are applied
+#[derive(Clone, Copy)]
+pub struct MaterialBase {
+    alpha: f32,
+    alpha_mode: AlphaMode,
+    depth_bias: f32,
+    /// Alpha and depth bias last written, to tell the layer's changes apart from other systems'
+    applied: (f32, f32),
+}
+
+/// Opacity and depth bias a layer's settings apply to its materials
+fn material_settings(settings: &LayerSettings) -> (f32, f32) {
+    (
+        settings.opacity.clamp(0.0, 1.0),
+        settings.z_order as f32 * Z_ORDER_DEPTH_BIAS,
+    )
+}
+
+/// System to apply [`Layers`] settings to the entities in each layer.
+///
+/// Layers outside their altitude range or switched off are hidden, opacity and z-order apply
+/// to [`Overlay`]s, and opacity applies to [`PointCloud`]s. Materials are handled by
+/// [`apply_layer_materials`], added once per material type.
+pub fn apply_layers(
+    mut layers: ResMut<Layers>,
+    new_layers: Query<&MapLayer, Added<MapLayer>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut roots: Query<(Entity, &MapLayer, &mut Visibility)>,
+    children: Query<&Children>,
+    mut overlays: Query<&mut Overlay>,
+    mut clouds: Query<&mut PointCloud>,
+) {
+    for layer in new_layers.iter() {
+        if layers.get(&layer.name).is_none() {
+            layers.register(&layer.name);
+        }
+    }
+    let altitude = cameras
+        .iter()
+        .find(|(camera, _)| camera.is_active)
+        .map(|(_, transform)| (transform.translation().length() - EARTH_RADIUS).max(0.0));
+
+    for (root, layer, mut visibility) in roots.iter_mut() {
+        let Some(settings) = layers.get(&layer.name) else {
+            continue;
+        };
+        let shown = match altitude {
+            Some(altitude) => settings.shown_at(altitude),
+            None => settings.visible,
+        };
+        let wanted = if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+
+        let (opacity, depth_bias) = material_settings(settings);
+        for entity in std::iter::once(root).chain(children.iter_descendants(root)) {
+            if let Ok(mut overlay) = overlays.get_mut(entity) {
+                if overlay.layer_opacity != opacity || overlay.layer_depth_bias != depth_bias {
+                    overlay.layer_opacity = opacity;
+                    overlay.layer_depth_bias = depth_bias;
+                }
+            }
+            if let Ok(mut cloud) = clouds.get_mut(entity) {
+                if cloud.opacity != opacity {
+                    cloud.opacity = opacity;
+                }
+            }
+        }
+    }
+}
+
+/// System to apply [`Layers`] opacity and z-order to the materials of type `M` of each layer's
+/// entities and their descendants.
+///
+/// Materials changed by other systems, e.g. a choropleth recoloring polygons, keep the new
+/// color as their base.
+pub fn apply_layer_materials<M: LayerMaterial>(
+    layers: Res<Layers>,
+    roots: Query<(Entity, &MapLayer)>,
+    children: Query<&Children>,
+    material_handles: Query<&Handle<M>, Without<OverlayPatch>>,
+    mut materials: ResMut<Assets<M>>,
+    mut bases: Local<HashMap<HandleId, MaterialBase>>,
+) {
+    for (root, layer) in roots.iter() {
+        let Some(settings) = layers.get(&layer.name) else {
+            continue;
+        };
+        let (opacity, depth_bias) = material_settings(settings);
+        for entity in std::iter::once(root).chain(children.iter_descendants(root)) {
+            let Ok(handle) = material_handles.get(entity) else {
+                continue;
+            };
+            let Some(material) = materials.get(handle) else {
+                continue;
+            };
+            let current = (material.alpha(), material.depth_bias());
+            let base = bases.entry(handle.id()).or_insert(MaterialBase {
+                alpha: current.0,
+                alpha_mode: material.alpha_mode(),
+                depth_bias: current.1,
+                applied: current,
+            });
+            if current != base.applied {
+                // Written by something else since
+                base.alpha = current.0;
+                base.depth_bias = current.1;
+            }
+            let target = (base.alpha * opacity, base.depth_bias + depth_bias);
+            base.applied = target;
+            if current == target {
+                continue;
+            }
+            let base = *base;
+            if let Some(material) = materials.get_mut(handle) {
+                material.set_alpha(target.0);
+                material.set_depth_bias(target.1);
+                material.set_alpha_mode(match base.alpha_mode {
+                    AlphaMode::Opaque if opacity < 1.0 => AlphaMode::Blend,
+                    alpha_mode => alpha_mode,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layer_registry() {
+        let mut layers = Layers::default();
+        layers.register("cities").z_order = 2;
+        layers.register(TERRAIN_LAYER);
+        let polygons = layers.register("countries");
+        polygons.z_order = 1;
+        polygons.max_altitude = 500.0;
+        assert_eq!(layers.register("cities").z_order, 2);
+
+        let order: Vec<&str> = layers.iter().map(|(name, _)| name).collect();
+        assert_eq!(order, vec![TERRAIN_LAYER, "countries", "cities"]);
+
+        let countries = layers.get("countries").unwrap();
+        assert!(countries.shown_at(100.0));
+        assert!(!countries.shown_at(800.0));
+        layers.get_mut("countries").unwrap().visible = false;
+        assert!(!layers.get("countries").unwrap().shown_at(100.0));
+        assert!(layers.get("rivers").is_none());
+    }
+}
@@ -1,2 +1,19 @@
+pub mod arc;
+pub mod cache;
+pub mod choropleth;
+pub mod cluster;
 pub mod errors;
+pub mod export;
+pub mod heatmap;
+pub mod labels;
+pub mod layers;
 pub mod map;
+pub mod overlay;
+pub mod point_cloud;
+pub mod points;
+pub mod polygons;
+#[cfg(feature = "elevation-gdal")]
+pub mod raster;
+pub mod raster_layer;
+pub mod scale;
+pub mod tiles;
@@ -1,7 +1,22 @@
 use bevy::pbr::CascadeShadowConfigBuilder;
 use bevy::prelude::*;
 
-use bevy_earth::map::{self, ArcLine, Coordinates};
+use bevy_earth::arc::{
+    self, ArcClicked, ArcDrawn, ArcFlow, ArcLayer, ArcLine, ArcLineMaterial, ArcLinePlugin,
+    ArcReveal, FlowPattern,
+};
+use bevy_earth::choropleth;
+use bevy_earth::cluster;
+use bevy_earth::heatmap;
+use bevy_earth::labels::{self, LabelSettings};
+use bevy_earth::layers::{self, Layers, MapLayer};
+use bevy_earth::map::{self, Coordinates};
+use bevy_earth::overlay;
+use bevy_earth::point_cloud::PointCloudPlugin;
+use bevy_earth::points::{self, PointLayer, PointTable, PointTableLoader};
+use bevy_earth::polygons;
+use bevy_earth::raster_layer;
+#[cfg(feature = "egui-ui")]
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_mod_picking::prelude::*;
 use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
@@ -16,12 +31,40 @@ fn main() {
         .add_plugin(PanOrbitCameraPlugin)
         .add_plugin(DebugLinesPlugin::default())
         .add_plugins(DefaultPickingPlugins)
+        .add_plugin(PointCloudPlugin)
+        .add_plugin(ArcLinePlugin)
+        .init_resource::<map::TerrainSettings>()
+        .init_resource::<LabelSettings>()
+        .init_resource::<Layers>()
+        .add_event::<ArcDrawn>()
+        .add_event::<ArcClicked>()
+        .add_asset::<PointTable>()
+        .init_asset_loader::<PointTableLoader>()
         .add_startup_system(spawn_scene)
         .add_startup_system(map::generate_faces)
+        .add_system(map::make_terrain_pickable)
         .add_startup_system(spawn_city_population_spheres)
         .add_startup_system(spawn_example_arc_lines)
         .add_startup_system(spawn_austin_arc_lines)
-        .add_system(map::spawn_arc_line_meshes)
+        .add_system(arc::reveal_arcs.before(arc::spawn_arc_line_meshes))
+        .add_system(arc::spawn_arc_line_meshes)
+        .add_system(arc::animate_arc_flows.after(arc::spawn_arc_line_meshes))
+        .add_system(arc::update_arc_layers)
+        .add_system(arc::pick_arc_layers)
+        .add_system(points::spawn_point_layers)
+        .add_system(points::reload_point_layers)
+        .add_system(points::log_point_clicks)
+        .add_system(points::spawn_point_labels)
+        .add_system(cluster::cluster_point_layers)
+        .add_system(labels::update_world_labels)
+        .add_system(polygons::spawn_polygon_layers)
+        .add_system(choropleth::apply_choropleths)
+        .add_system(heatmap::update_heatmap_layers)
+        .add_system(raster_layer::update_raster_layers)
+        .add_system(overlay::drape_overlays)
+        .add_system(layers::apply_layers)
+        .add_system(layers::apply_layer_materials::<StandardMaterial>)
+        .add_system(layers::apply_layer_materials::<ArcLineMaterial>)
         // .add_system(direction_lines)
         .run();
 }
@@ -52,114 +95,17 @@ fn spawn_visualization_bubbles(
     });
 }
 
-fn spawn_city_population_spheres(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-) {
-    // Cities data: (name, latitude, longitude, population in millions)
-    let major_cities: Vec<(String, f32, f32, f32)> = vec![
-        (String::from("Tokyo"), 35.6762, 139.6503, 37.4),
-        (String::from("Delhi"), 28.6139, 77.2090, 32.9),
-        (String::from("Shanghai"), 31.2304, 121.4737, 28.5),
-        (String::from("São Paulo"), -23.5505, -46.6333, 22.4),
-        (String::from("Mexico City"), 19.4326, -99.1332, 22.2),
-        (String::from("Cairo"), 30.0444, 31.2357, 21.3),
-        (String::from("Mumbai"), 19.0760, 72.8777, 20.7),
-        (String::from("Beijing"), 39.9042, 116.4074, 20.5),
-        (String::from("Dhaka"), 23.8103, 90.4125, 19.6),
-        (String::from("Osaka"), 34.6937, 135.5023, 19.2),
-        (String::from("New York"), 40.7128, -74.0060, 18.8),
-        (String::from("Karachi"), 24.8607, 67.0011, 16.5),
-        (String::from("Buenos Aires"), -34.6037, -58.3816, 15.2),
-        (String::from("Istanbul"), 41.0082, 28.9784, 15.1),
-        (String::from("Kolkata"), 22.5726, 88.3639, 14.9),
-        (String::from("Lagos"), 6.5244, 3.3792, 14.8),
-        (String::from("London"), 51.5074, -0.1278, 14.3),
-        (String::from("Los Angeles"), 34.0522, -118.2437, 13.2),
-        (String::from("Manila"), 14.5995, 120.9842, 13.1),
-        (String::from("Rio de Janeiro"), -22.9068, -43.1729, 13.0),
-        (String::from("Tianjin"), 39.3434, 117.3616, 12.8),
-        (String::from("Kinshasa"), -4.4419, 15.2663, 12.6),
-        (String::from("Paris"), 48.8566, 2.3522, 11.1),
-        (String::from("Shenzhen"), 22.5431, 114.0579, 10.6),
-        (String::from("Jakarta"), -6.2088, 106.8456, 10.6),
-        (String::from("Bangalore"), 12.9716, 77.5946, 10.5),
-        (String::from("Moscow"), 55.7558, 37.6173, 10.5),
-        (String::from("Chennai"), 13.0827, 80.2707, 10.0),
-        (String::from("Lima"), -12.0464, -77.0428, 9.7),
-        (String::from("Bangkok"), 13.7563, 100.5018, 9.6),
-        (String::from("Seoul"), 37.5665, 126.9780, 9.5),
-        (String::from("Hyderabad"), 17.3850, 78.4867, 9.5),
-        (String::from("Chengdu"), 30.5728, 104.0668, 9.3),
-        (String::from("Singapore"), 1.3521, 103.8198, 5.7),
-        (String::from("Ho Chi Minh City"), 10.8231, 106.6297, 9.1),
-        (String::from("Toronto"), 43.6532, -79.3832, 6.4),
-        (String::from("Sydney"), -33.8688, 151.2093, 5.3),
-        (String::from("Johannesburg"), -26.2041, 28.0473, 5.9),
-        (String::from("Chicago"), 41.8781, -87.6298, 8.9),
-        (String::from("Taipei"), 25.0330, 121.5654, 7.4),
-    ];
-
-    // Define constants for scaling the spheres
-    const BASE_RADIUS: f32 = 2.0; // Minimum radius for smallest city
-    const SCALE_FACTOR: f32 = 0.5; // Multiplier for population to radius conversion
-    const MIN_POPULATION: f32 = 5.0; // For normalization purposes
-    const MAX_POPULATION: f32 = 40.0; // For normalization purposes
-
-    // Create a component to store city information
-    #[derive(Component)]
-    struct CityMarker {
-        name: String,
-        population: f32,
-    }
-
-    // Create a mesh that will be reused for all cities
-    let sphere_mesh = meshes.add(
-        Mesh::try_from(shape::Icosphere {
-            radius: 1.0, // We'll scale this in the transform
-            subdivisions: 32,
-        })
-        .unwrap(),
-    );
-
-    // Spawn a sphere for each city
-    for (name, latitude, longitude, population) in major_cities {
-        // Convert latitude and longitude to 3D coordinates on the sphere
-        let coords = Coordinates::from_degrees(latitude, longitude)
-            .unwrap()
-            .get_point_on_sphere();
-
-        // Calculate sphere size based on population
-        // Using a logarithmic scale to prevent extremely large cities from dominating
-        let normalized_population =
-            (population - MIN_POPULATION) / (MAX_POPULATION - MIN_POPULATION);
-        let size = BASE_RADIUS + (normalized_population * SCALE_FACTOR * 10.0);
-
-        // Calculate color based on population (gradient from yellow to red)
-        let t = normalized_population.clamp(0.0, 1.0);
-        let color = Color::rgb(
-            1.0,             // Red stays at 1.0
-            1.0 - (t * 0.7), // Green decreases with population
-            0.5 - (t * 0.4), // Blue decreases with population
-        );
-
-        // Spawn the city sphere
-        commands.spawn((
-            PbrBundle {
-                mesh: sphere_mesh.clone(),
-                material: materials.add(StandardMaterial {
-                    base_color: color,
-                    unlit: true,
-                    ..default()
-                }),
-                transform: Transform::from_translation(Vec3::new(coords.x, coords.y, coords.z))
-                    .with_scale(Vec3::splat(size)),
-                ..default()
-            },
-            CityMarker { name, population },
-        ));
-    }
+fn spawn_city_population_spheres(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // Population in millions, mapped onto marker size and color
+    commands.spawn((
+        SpatialBundle::default(),
+        PointLayer::new(asset_server.load("data/cities.csv"))
+            .with_value_column("population")
+            .with_label_column("name")
+            .with_value_range(5.0, 40.0)
+            .with_clustering(30.0),
+        MapLayer::new("cities"),
+    ));
 }
 
 fn spawn_scene(
@@ -253,20 +199,49 @@ fn spawn_scene(
 
 fn spawn_example_arc_lines(mut commands: Commands) {
     // Example arc lines between major cities with varying heights
-    if let Ok(arc) = ArcLine::new(40.7128, -74.0060, 51.5074, -0.1278) { // New York to London
-        commands.spawn(arc.with_color(Color::RED).with_segments(60).with_arc_height(30.0));
+    if let Ok(arc) = ArcLine::new(40.7128, -74.0060, 51.5074, -0.1278) {
+        // New York to London
+        commands.spawn((
+            arc.with_color(Color::RED)
+                .with_segments(60)
+                .with_arc_height(30.0),
+            ArcReveal::new(2.0),
+            ArcFlow::new(FlowPattern::Pulses),
+            MapLayer::new("arcs"),
+        ));
     }
-    
-    if let Ok(arc) = ArcLine::new(35.6762, 139.6503, -34.6037, -58.3816) { // Tokyo to Buenos Aires
-        commands.spawn(arc.with_color(Color::BLUE).with_segments(80).with_arc_height(80.0));
+
+    if let Ok(arc) = ArcLine::new(35.6762, 139.6503, -34.6037, -58.3816) {
+        // Tokyo to Buenos Aires
+        commands.spawn((
+            arc.with_color(Color::BLUE)
+                .with_segments(80)
+                .with_arc_height(80.0),
+            ArcReveal::new(2.0).with_delay(2.0),
+            MapLayer::new("arcs"),
+        ));
     }
-    
-    if let Ok(arc) = ArcLine::new(55.7558, 37.6173, -33.8688, 151.2093) { // Moscow to Sydney
-        commands.spawn(arc.with_color(Color::GREEN).with_segments(70).with_arc_height(60.0));
+
+    if let Ok(arc) = ArcLine::new(55.7558, 37.6173, -33.8688, 151.2093) {
+        // Moscow to Sydney
+        commands.spawn((
+            arc.with_color(Color::GREEN)
+                .with_segments(70)
+                .with_arc_height(60.0),
+            ArcReveal::new(2.0).with_delay(4.0),
+            MapLayer::new("arcs"),
+        ));
     }
-    
-    if let Ok(arc) = ArcLine::new(19.4326, -99.1332, 28.6139, 77.2090) { // Mexico City to Delhi
-        commands.spawn(arc.with_color(Color::ORANGE).with_segments(65).with_arc_height(45.0));
+
+    if let Ok(arc) = ArcLine::new(19.4326, -99.1332, 28.6139, 77.2090) {
+        // Mexico City to Delhi
+        commands.spawn((
+            arc.with_color(Color::ORANGE)
+                .with_segments(65)
+                .with_arc_height(45.0),
+            ArcReveal::new(2.0).with_delay(6.0),
+            MapLayer::new("arcs"),
+        ));
     }
 }
 
@@ -275,59 +250,65 @@ fn spawn_austin_arc_lines(mut commands: Commands) {
     let austin_lat = 30.2672;
     let austin_lon = -97.7431;
     
-    // All major cities from the population system with "random" arc heights
-    let cities_with_heights = vec![
-        ("Tokyo", 35.6762, 139.6503, 85.0),
-        ("Delhi", 28.6139, 77.2090, 42.0),
-        ("Shanghai", 31.2304, 121.4737, 67.0),
-        ("São Paulo", -23.5505, -46.6333, 38.0),
-        ("Mexico City", 19.4326, -99.1332, 15.0), // Lower since it's closer
-        ("Cairo", 30.0444, 31.2357, 73.0),
-        ("Mumbai", 19.0760, 72.8777, 55.0),
-        ("Beijing", 39.9042, 116.4074, 91.0),
-        ("Dhaka", 23.8103, 90.4125, 29.0),
-        ("Osaka", 34.6937, 135.5023, 78.0),
-        ("New York", 40.7128, -74.0060, 22.0), // Lower since it's closer
-        ("Karachi", 24.8607, 67.0011, 46.0),
-        ("Buenos Aires", -34.6037, -58.3816, 51.0),
-        ("Istanbul", 41.0082, 28.9784, 64.0),
-        ("Kolkata", 22.5726, 88.3639, 33.0),
-        ("Lagos", 6.5244, 3.3792, 58.0),
-        ("London", 51.5074, -0.1278, 82.0),
-        ("Los Angeles", 34.0522, -118.2437, 8.0), // Very low since it's close
-        ("Manila", 14.5995, 120.9842, 76.0),
-        ("Rio de Janeiro", -22.9068, -43.1729, 44.0),
-        ("Tianjin", 39.3434, 117.3616, 89.0),
-        ("Kinshasa", -4.4419, 15.2663, 61.0),
-        ("Paris", 48.8566, 2.3522, 75.0),
-        ("Shenzhen", 22.5431, 114.0579, 68.0),
-        ("Jakarta", -6.2088, 106.8456, 72.0),
-        ("Bangalore", 12.9716, 77.5946, 37.0),
-        ("Moscow", 55.7558, 37.6173, 94.0),
-        ("Chennai", 13.0827, 80.2707, 41.0),
-        ("Lima", -12.0464, -77.0428, 26.0),
-        ("Bangkok", 13.7563, 100.5018, 53.0),
-        ("Seoul", 37.5665, 126.9780, 83.0),
-        ("Hyderabad", 17.3850, 78.4867, 35.0),
-        ("Chengdu", 30.5728, 104.0668, 69.0),
-        ("Singapore", 1.3521, 103.8198, 77.0),
-        ("Ho Chi Minh City", 10.8231, 106.6297, 48.0),
-        ("Toronto", 43.6532, -79.3832, 18.0), // Lower since it's closer
-        ("Sydney", -33.8688, 151.2093, 95.0),
-        ("Johannesburg", -26.2041, 28.0473, 62.0),
-        ("Chicago", 41.8781, -87.6298, 12.0), // Lower since it's closer
-        ("Taipei", 25.0330, 121.5654, 71.0),
+    // All major cities from the population system
+    let cities = vec![
+        ("Tokyo", 35.6762, 139.6503),
+        ("Delhi", 28.6139, 77.2090),
+        ("Shanghai", 31.2304, 121.4737),
+        ("São Paulo", -23.5505, -46.6333),
+        ("Mexico City", 19.4326, -99.1332),
+        ("Cairo", 30.0444, 31.2357),
+        ("Mumbai", 19.0760, 72.8777),
+        ("Beijing", 39.9042, 116.4074),
+        ("Dhaka", 23.8103, 90.4125),
+        ("Osaka", 34.6937, 135.5023),
+        ("New York", 40.7128, -74.0060),
+        ("Karachi", 24.8607, 67.0011),
+        ("Buenos Aires", -34.6037, -58.3816),
+        ("Istanbul", 41.0082, 28.9784),
+        ("Kolkata", 22.5726, 88.3639),
+        ("Lagos", 6.5244, 3.3792),
+        ("London", 51.5074, -0.1278),
+        ("Los Angeles", 34.0522, -118.2437),
+        ("Manila", 14.5995, 120.9842),
+        ("Rio de Janeiro", -22.9068, -43.1729),
+        ("Tianjin", 39.3434, 117.3616),
+        ("Kinshasa", -4.4419, 15.2663),
+        ("Paris", 48.8566, 2.3522),
+        ("Shenzhen", 22.5431, 114.0579),
+        ("Jakarta", -6.2088, 106.8456),
+        ("Bangalore", 12.9716, 77.5946),
+        ("Moscow", 55.7558, 37.6173),
+        ("Chennai", 13.0827, 80.2707),
+        ("Lima", -12.0464, -77.0428),
+        ("Bangkok", 13.7563, 100.5018),
+        ("Seoul", 37.5665, 126.9780),
+        ("Hyderabad", 17.3850, 78.4867),
+        ("Chengdu", 30.5728, 104.0668),
+        ("Singapore", 1.3521, 103.8198),
+        ("Ho Chi Minh City", 10.8231, 106.6297),
+        ("Toronto", 43.6532, -79.3832),
+        ("Sydney", -33.8688, 151.2093),
+        ("Johannesburg", -26.2041, 28.0473),
+        ("Chicago", 41.8781, -87.6298),
+        ("Taipei", 25.0330, 121.5654),
     ];
     
-    for (name, lat, lon, height) in cities_with_heights {
-        if let Ok(arc) = ArcLine::new(lat, lon, austin_lat, austin_lon) {
-            commands.spawn(arc
-                .with_color(Color::CYAN)
+    // Drawn together as a single mesh
+    // Rising higher the further away the city is
+    let arcs = cities.into_iter().filter_map(|(_name, lat, lon)| {
+        let arc = ArcLine::new(lat, lon, austin_lat, austin_lon).ok()?;
+        Some(
+            arc.with_color(Color::CYAN)
                 .with_segments(50)
-                .with_arc_height(height)
-            );
-        }
-    }
+                .with_auto_height(30.0, 5.0, 100.0),
+        )
+    });
+    commands.spawn((
+        SpatialBundle::default(),
+        ArcLayer::new(arcs),
+        MapLayer::new("arcs"),
+    ));
 }
 
 fn direction_lines(_time: Res<Time>, mut lines: ResMut<DebugLines>) {
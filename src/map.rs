@@ -1,44 +1,160 @@
 use std::f32::consts::PI;
 
 use crate::errors::CoordError;
+use crate::layers::{MapLayer, TERRAIN_LAYER};
 use bevy::prelude::*;
 use bevy::render::mesh::{self, PrimitiveTopology};
+#[cfg(feature = "picking")]
 use bevy_mod_picking::prelude::*;
-use gdal::errors::GdalError;
-use gdal::raster::ResampleAlg;
-use gdal::spatial_ref::{CoordTransform, SpatialRef};
-use gdal::Dataset;
 
-const EARTH_RADIUS: f32 = 300.0;
+use crate::cache::MeshCache;
+#[cfg(feature = "elevation-gdal")]
+use crate::raster::RasterData;
+use crate::tiles::{TileSet, CUBE_FACES};
+
+pub use crate::arc::{spawn_arc_line_meshes, ArcLine};
+
+/// Radius of the globe in world units
+pub const EARTH_RADIUS: f32 = 300.0;
+
+/// Mean radius of the Earth, for converting real distances to world units
+pub const EARTH_RADIUS_KM: f32 = 6371.0;
+
+/// Offsets passed to [`generate_face`] for the four patches that make up each cube face
+pub const PATCH_OFFSETS: [(f32, f32); 4] = [(0.0, 0.0), (0.0, 1.0), (1.0, 0.0), (1.0, 1.0)];
+
+/// Settings used by [`generate_faces`] to build the terrain
+#[derive(Resource)]
+pub struct TerrainSettings {
+    /// A GeoTIFF read through GDAL, or a tile directory written by `earth-tiler`
+    pub elevation_path: String,
+    /// Vertices along each edge of a patch
+    pub resolution: u32,
+    /// World units of displacement per metre of elevation
+    pub exaggeration: f64,
+    /// Directory for cached patch meshes, or `None` to always regenerate them
+    pub cache_dir: Option<String>,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            elevation_path: "assets/WorldElevation/ETOPO_2022_v1_60s_N90W180_surface.tif"
+                .to_string(),
+            resolution: 600,
+            exaggeration: 1.0 / 300.0,
+            cache_dir: Some("cache/terrain".to_string()),
+        }
+    }
+}
+
+/// Anything that can report the surface height, in metres, at a latitude/longitude in degrees.
+pub trait HeightSource {
+    fn height_at(&self, latitude: f64, longitude: f64) -> Option<f64>;
+}
+
+/// Height source for a perfectly smooth sphere, used when no elevation data is available.
+pub struct FlatSurface;
+
+impl HeightSource for FlatSurface {
+    fn height_at(&self, _latitude: f64, _longitude: f64) -> Option<f64> {
+        None
+    }
+}
+
+/// Marker for the cube-sphere mesh patches that make up the globe's surface
+#[derive(Component)]
+pub struct TerrainPatch;
+
+/// Opens the terrain's elevation source, falling back to a smooth sphere if it can't be read
+pub fn open_height_source(path: &str) -> Box<dyn HeightSource> {
+    open_height_source_with_detail(path, u32::MAX).unwrap_or_else(|err| {
+        warn!("{err}");
+        Box::new(FlatSurface)
+    })
+}
+
+/// Opens the terrain's elevation source, reading elevation tiles only as detailed as
+/// `samples_per_face_edge` samples along each cube face need.
+///
+/// Unlike [`open_height_source`], this doesn't fall back to a smooth sphere, so callers can
+/// tell when the elevation data couldn't be read.
+pub fn open_height_source_with_detail(
+    path: &str,
+    samples_per_face_edge: u32,
+) -> Result<Box<dyn HeightSource>, String> {
+    if std::path::Path::new(path).is_dir() {
+        match TileSet::open(path) {
+            Ok(tiles) => Ok(Box::new(tiles.with_detail(samples_per_face_edge))),
+            Err(err) => Err(format!("Failed to open elevation tiles in {path}: {err}")),
+        }
+    } else {
+        #[cfg(feature = "elevation-gdal")]
+        match RasterData::new(path) {
+            Ok(rs) => Ok(Box::new(rs)),
+            Err(err) => Err(format!("Failed to open elevation raster {path}: {err}")),
+        }
+        #[cfg(not(feature = "elevation-gdal"))]
+        Err(format!(
+            "Reading elevation raster {path} requires the `elevation-gdal` feature"
+        ))
+    }
+}
 
 pub fn generate_faces(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     asset_server: Res<AssetServer>,
+    settings: Res<TerrainSettings>,
 ) {
-    // Get raster map
-    let rs =
-        RasterData::new("assets/WorldElevation/ETOPO_2022_v1_60s_N90W180_surface.tif").unwrap();
-
-    let faces = vec![
-        Vec3::X,
-        Vec3::NEG_X,
-        Vec3::Y,
-        Vec3::NEG_Y,
-        Vec3::Z,
-        Vec3::NEG_Z,
-    ];
-
-    let offsets = vec![(0.0, 0.0), (0.0, 1.0), (1.0, 0.0), (1.0, 1.0)];
-
-    let _rng = rand::thread_rng();
-
-    for direction in faces {
-        for offset in &offsets {
+    // Get raster map, as detailed as the patches, which span half a cube face
+    let rs = open_height_source_with_detail(
+        &settings.elevation_path,
+        2 * settings.resolution.saturating_sub(1),
+    );
+    // Smooth fallback patches must not be cached under the dataset they failed to read
+    let cache_dir = settings.cache_dir.as_ref().filter(|_| rs.is_ok());
+    let rs = rs.unwrap_or_else(|err| {
+        warn!("{err}");
+        Box::new(FlatSurface)
+    });
+
+    let cache = cache_dir.and_then(|dir| {
+        MeshCache::new(
+            dir,
+            &settings.elevation_path,
+            settings.resolution,
+            settings.exaggeration,
+        )
+        .map_err(|err| warn!("Terrain mesh cache disabled: {err}"))
+        .ok()
+    });
+
+    for direction in CUBE_FACES {
+        for offset in &PATCH_OFFSETS {
+            let cached = cache
+                .as_ref()
+                .and_then(|cache| cache.load(direction, offset.0, offset.1));
+            let mesh = cached.unwrap_or_else(|| {
+                let mesh = generate_face(
+                    direction,
+                    settings.resolution,
+                    offset.0,
+                    offset.1,
+                    settings.exaggeration,
+                    rs.as_ref(),
+                );
+                if let Some(cache) = &cache {
+                    if let Err(err) = cache.store(direction, offset.0, offset.1, &mesh) {
+                        warn!("Failed to cache terrain patch: {err}");
+                    }
+                }
+                mesh
+            });
             commands.spawn((
                 PbrBundle {
-                    mesh: meshes.add(generate_face(direction, 600, offset.0, offset.1, &rs)),
+                    mesh: meshes.add(mesh),
                     material: materials.add(StandardMaterial {
                         base_color_texture: Some(
                             asset_server.load("WorldTextures/earth_color_10K.png"),
@@ -54,31 +170,43 @@ pub fn generate_faces(
                     }),
                     ..default()
                 },
-                PickableBundle::default(), // Makes the entity pickable
-                RaycastPickTarget::default(),
-                On::<Pointer<Click>>::run(|event: Listener<Pointer<Click>>| {
-                    info!("Clicked on entity {:?}", event);
-                    let hit = event.hit;
-                    if let Some(pos) = hit.position {
-                        let coords: Coordinates = pos.into();
-                        let (latitude, longitude) = coords.as_degrees();
-                        info!(
-                            "Latlon of selected point: Lat: {}, Lon: {}",
-                            latitude, longitude
-                        );
-                    }
-                }),
+                TerrainPatch,
+                MapLayer::new(TERRAIN_LAYER),
             ));
         }
     }
 }
 
+/// System to make newly spawned terrain patches clickable
+#[cfg(feature = "picking")]
+pub fn make_terrain_pickable(mut commands: Commands, query: Query<Entity, Added<TerrainPatch>>) {
+    for entity in query.iter() {
+        commands.entity(entity).insert((
+            PickableBundle::default(), // Makes the entity pickable
+            RaycastPickTarget::default(),
+            On::<Pointer<Click>>::run(|event: Listener<Pointer<Click>>| {
+                info!("Clicked on entity {:?}", event);
+                let hit = event.hit;
+                if let Some(pos) = hit.position {
+                    let coords: Coordinates = pos.into();
+                    let (latitude, longitude) = coords.as_degrees();
+                    info!(
+                        "Latlon of selected point: Lat: {}, Lon: {}",
+                        latitude, longitude
+                    );
+                }
+            }),
+        ));
+    }
+}
+
 pub fn generate_face(
     normal: Vec3,
     resolution: u32,
     x_offset: f32,
     y_offset: f32,
-    rs: &RasterData,
+    exaggeration: f64,
+    rs: &(impl HeightSource + ?Sized),
 ) -> Mesh {
     let axis_a = Vec3::new(normal.y, normal.z, normal.x); // Horizontal
     let axis_b = axis_a.cross(normal); // Vertical
@@ -99,9 +227,9 @@ pub fn generate_face(
             let point_coords: Coordinates = point_on_unit_cube.normalize().into();
             let (lat, lon) = point_coords.as_degrees();
 
-            let height_offset = rs.get_coordinate_height(lat as f64, lon as f64);
-            let normalized_point = if let Ok(Some(offset)) = height_offset {
-                let height = if offset > 0.0 { offset / 300.0 } else { 0.0 };
+            let height_offset = rs.height_at(lat as f64, lon as f64);
+            let normalized_point = if let Some(offset) = height_offset {
+                let height = offset.max(0.0) * exaggeration;
                 point_on_unit_cube.normalize() * (EARTH_RADIUS + (height) as f32)
             } else {
                 point_on_unit_cube.normalize() * EARTH_RADIUS
@@ -171,7 +299,7 @@ pub fn generate_mesh() -> Mesh {
     mesh
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Coordinates {
     // Stored internally in radians
     pub latitude: f32,
@@ -234,171 +362,145 @@ impl Coordinates {
 
     /// Calculate great circle arc between two coordinates with adjustable height
     pub fn arc_to(&self, other: &Coordinates, num_segments: u32, arc_height: f32) -> Vec<Vec3> {
-        let start_point = self.get_point_on_sphere().normalize();
-        let end_point = other.get_point_on_sphere().normalize();
-        
-        // Calculate the angle between the two points
-        let dot_product = start_point.dot(end_point).clamp(-1.0, 1.0);
-        let angle = dot_product.acos();
-        
-        // If points are very close, just return direct line
-        if angle < 0.001 {
-            return vec![
-                start_point * EARTH_RADIUS,
-                end_point * EARTH_RADIUS
-            ];
-        }
-        
-        let mut points = Vec::new();
-        
-        for i in 0..=num_segments {
-            let t = i as f32 / num_segments as f32;
-            
-            // Spherical linear interpolation (slerp)
-            let sin_angle = angle.sin();
-            let a = ((1.0 - t) * angle).sin() / sin_angle;
-            let b = (t * angle).sin() / sin_angle;
-            
-            let interpolated = (start_point * a + end_point * b).normalize();
-            
-            // Calculate height offset using a parabolic curve
-            // Height is 0 at endpoints (t=0 and t=1) and maximum at t=0.5
-            let height_multiplier = 4.0 * t * (1.0 - t); // Parabolic curve: peaks at t=0.5
-            let height_offset = arc_height * height_multiplier;
-            
-            // Apply the height offset
-            let radius = EARTH_RADIUS + height_offset;
-            points.push(interpolated * radius);
-        }
-        
-        points
+        self.arc_to_with(other, num_segments, arc_height, &AntipodalPath::default())
     }
-}
-
-/// Component to store arc line data
-#[derive(Component)]
-pub struct ArcLine {
-    pub from: Coordinates,
-    pub to: Coordinates,
-    pub color: Color,
-    pub segments: u32,
-    pub arc_height: f32,  // Height above the sphere surface at the arc's peak
-}
 
-impl ArcLine {
-    pub fn new(from_lat: f32, from_lon: f32, to_lat: f32, to_lon: f32) -> Result<Self, CoordError> {
-        Ok(Self {
-            from: Coordinates::from_degrees(from_lat, from_lon)?,
-            to: Coordinates::from_degrees(to_lat, to_lon)?,
-            color: Color::YELLOW,
-            segments: 50,
-            arc_height: 50.0,  // Default height above surface
+    /// Calculate great circle arc between two coordinates with adjustable height, taking the
+    /// path given by `antipodal` if they are on opposite sides of the globe.
+    ///
+    /// Identical coordinates give a single point.
+    pub fn arc_to_with(
+        &self,
+        other: &Coordinates,
+        num_segments: u32,
+        arc_height: f32,
+        antipodal: &AntipodalPath,
+    ) -> Vec<Vec3> {
+        // Height is 0 at endpoints (t=0 and t=1) and maximum at t=0.5
+        self.arc_to_with_heights(other, num_segments, antipodal, |t| {
+            arc_height * 4.0 * t * (1.0 - t)
         })
     }
 
-    pub fn with_color(mut self, color: Color) -> Self {
-        self.color = color;
-        self
+    /// Calculate great circle arc between two coordinates like [`Coordinates::arc_to_with`],
+    /// raised above the surface by `height` of the fraction of the way along the arc.
+    pub fn arc_to_with_heights(
+        &self,
+        other: &Coordinates,
+        num_segments: u32,
+        antipodal: &AntipodalPath,
+        height: impl Fn(f32) -> f32,
+    ) -> Vec<Vec3> {
+        let start_point = self.get_point_on_sphere().normalize();
+        let end_point = other.get_point_on_sphere().normalize();
+        if angle_between(start_point, end_point) < IDENTICAL_TOLERANCE {
+            return vec![start_point * EARTH_RADIUS];
+        }
+
+        let num_segments = num_segments.max(1);
+        (0..=num_segments)
+            .map(|i| {
+                let t = i as f32 / num_segments as f32;
+                self.interpolate(other, t, antipodal) * (EARTH_RADIUS + height(t))
+            })
+            .collect()
     }
 
-    pub fn with_segments(mut self, segments: u32) -> Self {
-        self.segments = segments;
-        self
+    /// Great-circle distance to `other` as an angle in radians
+    pub fn angle_to(&self, other: &Coordinates) -> f32 {
+        angle_between(
+            self.get_point_on_sphere().normalize(),
+            other.get_point_on_sphere().normalize(),
+        )
     }
 
-    pub fn with_arc_height(mut self, height: f32) -> Self {
-        self.arc_height = height;
-        self
+    /// Unit vector a fraction `t` of the way along the great circle to `other`.
+    ///
+    /// Any great circle through antipodal points joins them, so between points within
+    /// [`ANTIPODAL_TOLERANCE`] of opposite each other the one given by `antipodal` is taken.
+    pub fn interpolate(&self, other: &Coordinates, t: f32, antipodal: &AntipodalPath) -> Vec3 {
+        let start_point = self.get_point_on_sphere().normalize();
+        let end_point = other.get_point_on_sphere().normalize();
+        if angle_between(start_point, end_point) < PI - ANTIPODAL_TOLERANCE {
+            return slerp(start_point, end_point, t);
+        }
+
+        // Two well-conditioned slerps through a point off to the side instead
+        let via = antipodal.via_point(start_point);
+        let first = angle_between(start_point, via);
+        let second = angle_between(via, end_point);
+        let distance = t * (first + second);
+        if distance <= first {
+            slerp(start_point, via, distance / first)
+        } else {
+            slerp(via, end_point, (distance - first) / second)
+        }
     }
 }
 
-/// System to spawn arc line meshes
-pub fn spawn_arc_line_meshes(
-    mut commands: Commands,
-    query: Query<(Entity, &ArcLine), Added<ArcLine>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-) {
-    for (entity, arc) in query.iter() {
-        let points = arc.from.arc_to(&arc.to, arc.segments, arc.arc_height);
-        let line_mesh = create_line_mesh(&points, 1.0); // Line thickness
-        
-        commands.entity(entity).insert(PbrBundle {
-            mesh: meshes.add(line_mesh),
-            material: materials.add(StandardMaterial {
-                base_color: arc.color,
-                unlit: true,
-                alpha_mode: AlphaMode::Blend,
-                ..default()
-            }),
-            ..default()
-        });
+/// Endpoints closer than this angle in radians are treated as identical
+const IDENTICAL_TOLERANCE: f32 = 1e-6;
+
+/// Endpoints within this angle in radians of being antipodal follow an [`AntipodalPath`], as
+/// the great circle between them is undefined or unstable
+pub const ANTIPODAL_TOLERANCE: f32 = 1e-3;
+
+/// Which way an arc goes between antipodal, or nearly antipodal, points
+#[derive(Debug, Clone, Copy)]
+pub enum AntipodalPath {
+    /// Leave the start with this bearing in degrees clockwise from north. From a pole,
+    /// bearings are measured as if standing on the prime meridian.
+    Bearing(f32),
+    /// Pass through this point. Points too close to either end fall back to the default.
+    Via(Coordinates),
+}
+
+impl Default for AntipodalPath {
+    /// Head north from the start, over the North Pole
+    fn default() -> Self {
+        AntipodalPath::Bearing(0.0)
     }
 }
 
-/// Create a mesh representing a line with thickness (double-sided)
-fn create_line_mesh(points: &[Vec3], thickness: f32) -> Mesh {
-    if points.len() < 2 {
-        return Mesh::new(PrimitiveTopology::TriangleList);
+impl AntipodalPath {
+    /// Unit vector the arc from `start` passes through
+    fn via_point(&self, start: Vec3) -> Vec3 {
+        match self {
+            AntipodalPath::Bearing(bearing) => {
+                let east = Vec3::Y.cross(start).try_normalize().unwrap_or(Vec3::X);
+                let north = start.cross(east).normalize();
+                let (sin, cos) = bearing.to_radians().sin_cos();
+                north * cos + east * sin
+            }
+            AntipodalPath::Via(coordinates) => {
+                let via = coordinates.get_point_on_sphere().normalize();
+                let angle = angle_between(start, via);
+                if (ANTIPODAL_TOLERANCE..=PI - ANTIPODAL_TOLERANCE).contains(&angle) {
+                    via
+                } else {
+                    AntipodalPath::default().via_point(start)
+                }
+            }
+        }
     }
+}
 
-    let mut vertices = Vec::new();
-    let mut normals = Vec::new();
-    let mut indices = Vec::new();
-    let mut uvs = Vec::new();
+/// Angle in radians between unit vectors, accurate for nearly parallel and opposite vectors too
+fn angle_between(a: Vec3, b: Vec3) -> f32 {
+    a.cross(b).length().atan2(a.dot(b))
+}
 
-    for i in 0..(points.len() - 1) {
-        let start = points[i];
-        let end = points[i + 1];
-        
-        // Calculate direction and perpendicular vectors for the line segment
-        let direction = (end - start).normalize();
-        let to_center = -start.normalize(); // Vector pointing toward earth center
-        let perpendicular = direction.cross(to_center).normalize();
-        
-        // Create a quad for this line segment
-        let half_thickness = thickness * 0.5;
-        
-        // Four corners of the quad
-        let v0 = start - perpendicular * half_thickness;
-        let v1 = start + perpendicular * half_thickness;
-        let v2 = end + perpendicular * half_thickness;
-        let v3 = end - perpendicular * half_thickness;
-        
-        let base_index = vertices.len() as u32;
-        
-        // Add vertices for front-facing quad (outward normals)
-        vertices.extend_from_slice(&[v0, v1, v2, v3]);
-        let outward_normal = start.normalize();
-        normals.extend_from_slice(&[outward_normal, outward_normal, outward_normal, outward_normal]);
-        uvs.extend_from_slice(&[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
-        
-        // Add indices for front-facing triangles
-        indices.extend_from_slice(&[
-            base_index, base_index + 1, base_index + 2,
-            base_index, base_index + 2, base_index + 3,
-        ]);
-        
-        // Add vertices for back-facing quad (inward normals)
-        vertices.extend_from_slice(&[v0, v1, v2, v3]);
-        let inward_normal = -start.normalize();
-        normals.extend_from_slice(&[inward_normal, inward_normal, inward_normal, inward_normal]);
-        uvs.extend_from_slice(&[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
-        
-        // Add indices for back-facing triangles (reversed winding order)
-        let back_base = base_index + 4;
-        indices.extend_from_slice(&[
-            back_base, back_base + 2, back_base + 1,
-            back_base, back_base + 3, back_base + 2,
-        ]);
+/// Spherical linear interpolation between unit vectors that aren't antipodal
+fn slerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    let angle = angle_between(a, b);
+    let sin_angle = angle.sin();
+    if sin_angle < 1e-4 {
+        // Too close to divide by, and close enough to interpolate linearly
+        return a.lerp(b, t).try_normalize().unwrap_or(a);
     }
-
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    mesh.set_indices(Some(mesh::Indices::U32(indices)));
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-    mesh
+    let a_weight = ((1.0 - t) * angle).sin() / sin_angle;
+    let b_weight = (t * angle).sin() / sin_angle;
+    (a * a_weight + b * b_weight).normalize()
 }
 
 fn map_latitude(lat: f32) -> Result<f32, CoordError> {
@@ -437,77 +539,8 @@ fn map(range_a: (f32, f32), range_b: (f32, f32), value: f32) -> f32 {
     range_b.0 + (value - range_a.0) * (range_b.1 - range_b.0) / (range_a.1 - range_a.0)
 }
 
-pub struct RasterData {
-    pub dataset: Dataset,
-    pub transform: CoordTransform,
-}
-
-impl RasterData {
-    pub fn new(path: &str) -> Result<Self, GdalError> {
-        let dataset = Dataset::open(path)?;
-        let srs = dataset.spatial_ref()?;
-        let target_srs = SpatialRef::from_epsg(4326)?;
-        let transform = gdal::spatial_ref::CoordTransform::new(&srs, &target_srs)?;
-        Ok(Self { dataset, transform })
-    }
-    pub fn get_coordinate_height(
-        &self,
-        latitude: f64,
-        longitude: f64,
-    ) -> Result<Option<f64>, GdalError> {
-        let (lat, lon) = (latitude, longitude);
-        self.transform
-            .transform_coords(&mut [lon], &mut [lat], &mut [])?;
-        let raster_band = self.dataset.rasterband(1)?;
-        let transform = self.dataset.geo_transform().unwrap();
-        let x = (lon - transform[0]) / transform[1];
-        let y = (lat - transform[3]) / transform[5];
-        let mut res_buffer = raster_band.read_as::<f64>(
-            (x as isize, y as isize),
-            (1, 1),
-            (1, 1),
-            Some(ResampleAlg::Average),
-        )?;
-        Ok(res_buffer.data.pop())
-    }
-}
-
-pub fn load_tiff() {
-    let ds = Dataset::open("assets/WorldElevation/black_sea.tif").unwrap();
-
-    let tgt_latitude = 44.579543;
-    let tgt_longitude = 33.396264;
-
-    println!(
-        "This {} is in '{}' and has {} bands.",
-        ds.driver().long_name(),
-        ds.spatial_ref().unwrap().name().unwrap(),
-        ds.raster_count()
-    );
-    let srs = ds.spatial_ref().unwrap();
-    let target_srs = SpatialRef::from_epsg(4326).unwrap();
-    let transform = gdal::spatial_ref::CoordTransform::new(&srs, &target_srs).unwrap();
-    transform
-        .transform_coords(&mut [tgt_longitude], &mut [tgt_latitude], &mut [])
-        .unwrap();
-    println!("Target coords: {tgt_latitude}, {tgt_longitude}");
-    let raster_band = ds.rasterband(1).unwrap();
-    let mut res_buffer = raster_band
-        .read_as::<f64>(
-            (tgt_latitude as isize, tgt_longitude as isize),
-            (1, 1),
-            (1, 1),
-            None,
-        )
-        .unwrap();
-    let value = res_buffer.data.pop().unwrap();
-    println!("Value: {value:?}");
-}
-
 #[cfg(test)]
 mod tests {
-    use gdal::{programs::raster, raster::ResampleAlg};
-
     use super::*;
     use crate::map::{map_latitude, map_longitude};
 
@@ -541,21 +574,38 @@ mod tests {
     }
 
     #[test]
-    fn test_raster_map() {
-        let raster_data =
-            RasterData::new("assets/Bathymetry/gebco_2023_n47.7905_s39.9243_w25.6311_e42.9895.tif")
-                .unwrap();
-
-        // Mt Elbrus
-        let tgt_latitude = 43.351851;
-        let tgt_longitude = 42.4368771;
-
-        let elevation = raster_data
-            .get_coordinate_height(tgt_latitude, tgt_longitude)
-            .unwrap()
-            .unwrap();
-
-        assert_eq!(elevation, 5392.0);
+    fn test_arc_endpoints() {
+        let austin = Coordinates::from_degrees(30.2672, -97.7431).unwrap();
+        assert_eq!(austin.arc_to(&austin, 10, 50.0).len(), 1);
+
+        // Adjacent points still give evenly spaced, finite points
+        let nearby = Coordinates::from_degrees(30.2672, -97.7430).unwrap();
+        let points = austin.arc_to(&nearby, 10, 0.0);
+        assert_eq!(points.len(), 11);
+        assert!(points.iter().all(|point| point.is_finite()));
+        assert!(points[0].distance(austin.get_point_on_sphere()) < 1e-3);
+        assert!(points[10].distance(nearby.get_point_on_sphere()) < 1e-3);
+
+        // Antipodal points go over the North Pole unless told otherwise
+        let null_island = Coordinates::from_degrees(0.0, 0.0).unwrap();
+        let antipode = Coordinates::from_degrees(0.0, 180.0).unwrap();
+        let points = null_island.arc_to(&antipode, 10, 0.0);
+        assert!(points.iter().all(|point| point.is_finite()));
+        assert!(points[5].normalize().distance(Vec3::Y) < 1e-5);
+        assert!(points[10].distance(antipode.get_point_on_sphere()) < 1e-3);
+        let points = null_island.arc_to_with(&antipode, 10, 0.0, &AntipodalPath::Bearing(90.0));
+        assert!(points[5].normalize().distance(Vec3::X) < 1e-5);
+        let via = AntipodalPath::Via(Coordinates::from_degrees(0.0, -90.0).unwrap());
+        let points = null_island.arc_to_with(&antipode, 10, 0.0, &via);
+        assert!(points[5].normalize().distance(Vec3::NEG_X) < 1e-5);
+
+        // Nearly antipodal points too, with every step the same length
+        let almost = Coordinates::from_degrees(-0.00001, 180.0).unwrap();
+        let points = null_island.arc_to(&almost, 10, 0.0);
+        let step = points[0].distance(points[1]);
+        assert!(points
+            .windows(2)
+            .all(|pair| (pair[0].distance(pair[1]) - step).abs() < 1e-2));
+        assert!(points[10].distance(almost.get_point_on_sphere()) < 1e-3);
     }
-
 }
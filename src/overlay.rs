@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::utils::HashSet;
+
+use crate::map::TerrainPatch;
+
+/// Draws overlays in front of the terrain they share their meshes with
+const OVERLAY_DEPTH_BIAS: f32 = 5.0;
+
+/// Drapes an equirectangular image over every terrain patch, blended by its alpha.
+///
+/// The image spans longitudes -180 to 180 from left to right and latitudes 90 to -90 from top
+/// to bottom, the same mapping as the terrain's color texture. Add it to an entity with a
+/// `SpatialBundle`; the draped patches are spawned as its children.
+#[derive(Component)]
+pub struct Overlay {
+    pub image: Handle<Image>,
+    /// Multiplies the image's own alpha
+    pub opacity: f32,
+    /// Opacity and depth bias of the overlay's map layer, see [`crate::layers`]
+    pub(crate) layer_opacity: f32,
+    pub(crate) layer_depth_bias: f32,
+    material: Option<Handle<StandardMaterial>>,
+}
+
+impl Overlay {
+    pub fn new(image: Handle<Image>) -> Self {
+        Self {
+            image,
+            opacity: 1.0,
+            layer_opacity: 1.0,
+            layer_depth_bias: 0.0,
+            material: None,
+        }
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity;
+        self
+    }
+}
+
+/// Builds an image for an [`Overlay`] from the color at the latitude and longitude in degrees of
+/// each pixel's center
+pub fn equirectangular_image(
+    width: usize,
+    height: usize,
+    mut color_at: impl FnMut(f64, f64) -> Color,
+) -> Image {
+    let mut data = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let latitude = 90.0 - (y as f64 + 0.5) * 180.0 / height as f64;
+        for x in 0..width {
+            let longitude = -180.0 + (x as f64 + 0.5) * 360.0 / width as f64;
+            let color = color_at(latitude, longitude).as_rgba_f32();
+            data.extend(color.map(|channel| (channel * 255.0).round() as u8));
+        }
+    }
+    Image::new(
+        Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// Copy of a terrain patch drawn with an [`Overlay`]'s image
+#[derive(Component)]
+pub struct OverlayPatch;
+
+fn overlay_material(overlay: &Overlay) -> StandardMaterial {
+    StandardMaterial {
+        base_color: Color::rgba(
+            1.0,
+            1.0,
+            1.0,
+            (overlay.opacity * overlay.layer_opacity).clamp(0.0, 1.0),
+        ),
+        base_color_texture: Some(overlay.image.clone()),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        depth_bias: OVERLAY_DEPTH_BIAS + overlay.layer_depth_bias,
+        ..default()
+    }
+}
+
+fn spawn_overlay_patch(
+    commands: &mut Commands,
+    overlay: Entity,
+    mesh: &Handle<Mesh>,
+    material: &Handle<StandardMaterial>,
+) {
+    commands.entity(overlay).with_children(|parent| {
+        parent.spawn((
+            PbrBundle {
+                mesh: mesh.clone(),
+                material: material.clone(),
+                ..default()
+            },
+            OverlayPatch,
+        ));
+    });
+}
+
+/// System to drape overlays over the terrain as either of them is spawned, to update an
+/// overlay's material when its image or opacity changes, and to despawn its patches when it is
+/// removed
+pub fn drape_overlays(
+    mut commands: Commands,
+    mut overlays: Query<(Entity, &mut Overlay)>,
+    patches: Query<&Handle<Mesh>, With<TerrainPatch>>,
+    new_patches: Query<&Handle<Mesh>, Added<TerrainPatch>>,
+    overlay_patches: Query<(Entity, &Parent), With<OverlayPatch>>,
+    mut removed: RemovedComponents<Overlay>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let removed: HashSet<Entity> = removed.iter().collect();
+    if !removed.is_empty() {
+        for (patch, parent) in overlay_patches.iter() {
+            if removed.contains(&parent.get()) {
+                commands.entity(patch).despawn_recursive();
+            }
+        }
+    }
+
+    for (entity, mut overlay) in overlays.iter_mut() {
+        match &overlay.material {
+            None => {
+                // New overlays cover every patch, including any spawned this frame
+                let material = materials.add(overlay_material(&overlay));
+                for mesh in patches.iter() {
+                    spawn_overlay_patch(&mut commands, entity, mesh, &material);
+                }
+                overlay.material = Some(material);
+            }
+            Some(material) => {
+                for mesh in new_patches.iter() {
+                    spawn_overlay_patch(&mut commands, entity, mesh, material);
+                }
+                if overlay.is_changed() {
+                    if let Some(material) = materials.get_mut(material) {
+                        *material = overlay_material(&overlay);
+                    }
+                }
+            }
+        }
+    }
+}
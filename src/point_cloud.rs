@@ -0,0 +1,602 @@
+use bevy::asset::load_internal_asset;
+use bevy::core_pipeline::core_3d::Transparent3d;
+use bevy::ecs::system::{lifetimeless::*, SystemParamItem};
+use bevy::pbr::{
+    MeshPipeline, MeshPipelineKey, MeshUniform, SetMeshBindGroup, SetMeshViewBindGroup,
+};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::mesh::{GpuBufferInfo, MeshVertexBufferLayout};
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_phase::{
+    AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult, RenderPhase,
+    SetItemPipeline, TrackedRenderPass,
+};
+use bevy::render::render_resource::*;
+use bevy::render::renderer::RenderDevice;
+use bevy::render::view::ExtractedView;
+use bevy::render::{Extract, ExtractSchedule, RenderApp, RenderSet};
+use bevy::utils::{HashMap, HashSet};
+use bevy::window::PrimaryWindow;
+use bytemuck::{Pod, Zeroable};
+
+use crate::map::EARTH_RADIUS;
+
+const POINT_CLOUD_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x5be1_7c2a_93d0_4e61);
+
+/// Cursor movement in pixels between press and release beyond which a click counts as a drag
+const CLICK_TOLERANCE: f32 = 4.0;
+
+/// Per-instance data uploaded to the GPU for a [`PointCloud`]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct PointInstance {
+    pub position: Vec3,
+    /// Horizontal scale of the mesh
+    pub scale: f32,
+    pub color: [f32; 4],
+    /// Scale of the mesh along the local up vector
+    pub height: f32,
+}
+
+impl PointInstance {
+    pub fn new(position: Vec3, scale: f32, color: Color) -> Self {
+        Self {
+            position,
+            scale,
+            color: color.as_rgba_f32(),
+            height: scale,
+        }
+    }
+
+    /// A column of the given radius standing `height` tall on `base`, for a cloud with a
+    /// [`PointShape::Column`] mesh
+    pub fn column(base: Vec3, radius: f32, height: f32, color: Color) -> Self {
+        Self {
+            position: base + base.normalize_or_zero() * height / 2.0,
+            scale: radius,
+            color: color.as_rgba_f32(),
+            height: height / 2.0,
+        }
+    }
+
+    /// Point where the instance meets the surface below it
+    pub fn base(&self) -> Vec3 {
+        self.position - self.position.normalize_or_zero() * self.height
+    }
+}
+
+/// Local east, up and north axes at a position, matching the orientation of instances in
+/// `point_cloud.wgsl`. The mesh's Y axis points away from the origin.
+pub fn instance_basis(position: Vec3) -> Mat3 {
+    let up = position.try_normalize().unwrap_or(Vec3::Y);
+    let helper = if up.y.abs() < 0.99 { Vec3::Y } else { Vec3::X };
+    let east = helper.cross(up).normalize();
+    let north = up.cross(east);
+    Mat3::from_cols(east, up, north)
+}
+
+/// Shape of the mesh a [`PointCloud`] draws, used for picking
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PointShape {
+    /// Fits in a unit sphere, like an icosphere of radius 1
+    #[default]
+    Sphere,
+    /// Fits in a cylinder of radius 1 from -1 to 1 along Y, like `shape::Cylinder` with radius
+    /// 1 and height 2
+    Column,
+}
+
+/// Draws the entity's mesh once per instance in a single draw call, unlit apart from a little
+/// shading by how much each face points away from the globe.
+///
+/// Each instance scales the mesh by `scale` across and `height` along its local up vector,
+/// so the mesh's Y axis points away from the origin. Picking treats every instance as its
+/// `shape`, scaled the same way. Entities need `NoFrustumCulling`, since their mesh bounds
+/// say nothing about where the instances are.
+#[derive(Component, Clone)]
+pub struct PointCloud {
+    pub instances: Vec<PointInstance>,
+    pub shape: PointShape,
+    /// Multiplies the alpha of every instance. Clouds with any translucent instances are
+    /// blended with what is behind them instead of drawn opaque. Clouds in a
+    /// [`MapLayer`](crate::layers::MapLayer) get the layer's opacity.
+    pub opacity: f32,
+}
+
+impl Default for PointCloud {
+    fn default() -> Self {
+        Self {
+            instances: Vec::new(),
+            shape: PointShape::default(),
+            opacity: 1.0,
+        }
+    }
+}
+
+impl PointCloud {
+    /// Instances as uploaded to the GPU, with the cloud's opacity applied
+    fn faded_instances(&self) -> Vec<PointInstance> {
+        let opacity = self.opacity.clamp(0.0, 1.0);
+        self.instances
+            .iter()
+            .map(|instance| {
+                let mut instance = *instance;
+                instance.color[3] *= opacity;
+                instance
+            })
+            .collect()
+    }
+}
+
+/// Sent when a point of a [`PointCloud`] is clicked
+#[derive(Debug)]
+pub struct PointCloudClicked {
+    pub cloud: Entity,
+    /// Index into the cloud's `instances`
+    pub index: usize,
+}
+
+/// Renders [`PointCloud`]s and sends [`PointCloudClicked`] events.
+///
+/// This has to be a plugin because the instanced draw is set up in the render sub-app. Add it
+/// after `DefaultPlugins`, which provide the mesh pipeline it builds on.
+pub struct PointCloudPlugin;
+
+impl Plugin for PointCloudPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            POINT_CLOUD_SHADER_HANDLE,
+            "point_cloud.wgsl",
+            Shader::from_wgsl
+        );
+        app.add_event::<PointCloudClicked>()
+            .add_system(pick_point_clouds);
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .add_render_command::<Transparent3d, DrawPointCloud>()
+            .init_resource::<PointCloudBuffers>()
+            .init_resource::<PointCloudPipeline>()
+            .init_resource::<SpecializedMeshPipelines<PointCloudPipeline>>()
+            .add_system(extract_point_clouds.in_schedule(ExtractSchedule))
+            .add_system(prepare_point_cloud_buffers.in_set(RenderSet::Prepare))
+            .add_system(queue_point_clouds.in_set(RenderSet::Queue));
+    }
+}
+
+/// Marks render world entities extracted from a [`PointCloud`]
+#[derive(Component)]
+struct ExtractedPointCloud;
+
+struct InstanceBuffer {
+    buffer: Buffer,
+    length: u32,
+    /// Whether any instance is drawn with alpha blending
+    translucent: bool,
+}
+
+/// Instance buffers by entity, kept across frames so a cloud is only uploaded when it changes
+#[derive(Resource, Default)]
+struct PointCloudBuffers {
+    buffers: HashMap<Entity, InstanceBuffer>,
+    pending: Vec<(Entity, Vec<PointInstance>)>,
+}
+
+fn extract_point_clouds(
+    mut commands: Commands,
+    mut buffers: ResMut<PointCloudBuffers>,
+    clouds: Extract<Query<Entity, With<PointCloud>>>,
+    changed: Extract<Query<(Entity, &PointCloud), Changed<PointCloud>>>,
+) {
+    let live: HashSet<Entity> = clouds.iter().collect();
+    buffers.buffers.retain(|entity, _| live.contains(entity));
+    commands.insert_or_spawn_batch(
+        live.into_iter()
+            .map(|entity| (entity, ExtractedPointCloud))
+            .collect::<Vec<_>>(),
+    );
+    for (entity, cloud) in changed.iter() {
+        buffers.pending.push((entity, cloud.faded_instances()));
+    }
+}
+
+fn prepare_point_cloud_buffers(
+    mut buffers: ResMut<PointCloudBuffers>,
+    render_device: Res<RenderDevice>,
+) {
+    let PointCloudBuffers { buffers, pending } = &mut *buffers;
+    for (entity, instances) in pending.drain(..) {
+        if instances.is_empty() {
+            buffers.remove(&entity);
+            continue;
+        }
+        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("point cloud instance buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: BufferUsages::VERTEX,
+        });
+        buffers.insert(
+            entity,
+            InstanceBuffer {
+                buffer,
+                length: instances.len() as u32,
+                translucent: instances.iter().any(|instance| instance.color[3] < 1.0),
+            },
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_point_clouds(
+    transparent_3d_draw_functions: Res<DrawFunctions<Transparent3d>>,
+    point_cloud_pipeline: Res<PointCloudPipeline>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<PointCloudPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    buffers: Res<PointCloudBuffers>,
+    clouds: Query<(Entity, &MeshUniform, &Handle<Mesh>), With<ExtractedPointCloud>>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
+) {
+    let draw_point_cloud = transparent_3d_draw_functions.read().id::<DrawPointCloud>();
+    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+
+    for (view, mut transparent_phase) in &mut views {
+        let view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
+        let rangefinder = view.rangefinder3d();
+        for (entity, mesh_uniform, mesh_handle) in &clouds {
+            let Some(mesh) = meshes.get(mesh_handle) else {
+                continue;
+            };
+            let Some(instances) = buffers.buffers.get(&entity) else {
+                continue;
+            };
+            let mut key =
+                view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            if instances.translucent {
+                key |= MeshPipelineKey::BLEND_ALPHA;
+            }
+            let pipeline = match pipelines.specialize(
+                &pipeline_cache,
+                &point_cloud_pipeline,
+                key,
+                &mesh.layout,
+            ) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("Failed to specialize point cloud pipeline: {err}");
+                    continue;
+                }
+            };
+            transparent_phase.add(Transparent3d {
+                entity,
+                pipeline,
+                draw_function: draw_point_cloud,
+                distance: rangefinder.distance(&mesh_uniform.transform),
+            });
+        }
+    }
+}
+
+#[derive(Resource)]
+struct PointCloudPipeline {
+    mesh_pipeline: MeshPipeline,
+}
+
+impl FromWorld for PointCloudPipeline {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for PointCloudPipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+        descriptor.vertex.shader = POINT_CLOUD_SHADER_HANDLE.typed();
+        descriptor.vertex.buffers.push(VertexBufferLayout {
+            array_stride: std::mem::size_of::<PointInstance>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![
+                // Past the locations the mesh pipeline uses for mesh attributes
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: 8,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: VertexFormat::Float32x4.size(),
+                    shader_location: 9,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32,
+                    offset: VertexFormat::Float32x4.size() * 2,
+                    shader_location: 10,
+                },
+            ],
+        });
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader = POINT_CLOUD_SHADER_HANDLE.typed();
+        }
+        Ok(descriptor)
+    }
+}
+
+type DrawPointCloud = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    DrawMeshInstanced,
+);
+
+struct DrawMeshInstanced;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<PointCloudBuffers>);
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = Read<Handle<Mesh>>;
+
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        mesh_handle: &'w Handle<Mesh>,
+        (meshes, buffers): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let (Some(gpu_mesh), Some(instances)) = (
+            meshes.into_inner().get(mesh_handle),
+            buffers.into_inner().buffers.get(&item.entity()),
+        ) else {
+            return RenderCommandResult::Failure;
+        };
+
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instances.buffer.slice(..));
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed {
+                buffer,
+                index_format,
+                count,
+            } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*count, 0, 0..instances.length);
+            }
+            GpuBufferInfo::NonIndexed { vertex_count } => {
+                pass.draw(0..*vertex_count, 0..instances.length);
+            }
+        }
+        RenderCommandResult::Success
+    }
+}
+
+/// Distance along a ray to where it enters a sphere, if it hits it
+pub fn ray_sphere_distance(ray: &Ray, center: Vec3, radius: f32) -> Option<f32> {
+    let offset = ray.origin - center;
+    let b = offset.dot(ray.direction);
+    let c = offset.length_squared() - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    [-b - root, -b + root].into_iter().find(|t| *t >= 0.0)
+}
+
+/// Distance along a ray to where it enters a cylinder of radius 1 from -1 to 1 along Y, if it
+/// hits it. The ray's direction doesn't have to be normalized; distances are in multiples of it.
+fn ray_unit_cylinder_distance(origin: Vec3, direction: Vec3) -> Option<f32> {
+    let mut hits = Vec::new();
+    // Side wall
+    let a = direction.x * direction.x + direction.z * direction.z;
+    let b = origin.x * direction.x + origin.z * direction.z;
+    let c = origin.x * origin.x + origin.z * origin.z - 1.0;
+    let discriminant = b * b - a * c;
+    if a > f32::EPSILON && discriminant >= 0.0 {
+        let root = discriminant.sqrt();
+        hits.extend([(-b - root) / a, (-b + root) / a].into_iter().filter(|t| {
+            let y = origin.y + direction.y * t;
+            (-1.0..=1.0).contains(&y)
+        }));
+    }
+    // End caps
+    if direction.y.abs() > f32::EPSILON {
+        hits.extend(
+            [-1.0, 1.0]
+                .into_iter()
+                .map(|y| (y - origin.y) / direction.y)
+                .filter(|t| {
+                    let point = origin + direction * *t;
+                    point.x * point.x + point.z * point.z <= 1.0
+                }),
+        );
+    }
+    // Rays starting inside hit immediately
+    if c <= 0.0 && origin.y.abs() <= 1.0 {
+        hits.push(0.0);
+    }
+    hits.into_iter()
+        .filter(|t| *t >= 0.0)
+        .min_by(f32::total_cmp)
+}
+
+/// Index and distance of the closest instance of a cloud hit by a ray
+pub fn pick_instance(
+    ray: &Ray,
+    cloud: &PointCloud,
+    transform: &GlobalTransform,
+) -> Option<(usize, f32)> {
+    // Pick in the cloud's space, where distances along the ray stay the same as long as the
+    // direction isn't renormalized
+    let inverse = transform.compute_matrix().inverse();
+    let origin = inverse.transform_point3(ray.origin);
+    let direction = inverse.transform_vector3(ray.direction);
+    cloud
+        .instances
+        .iter()
+        .enumerate()
+        .filter(|(_, instance)| instance.scale > 0.0 && instance.height > 0.0)
+        .filter_map(|(index, instance)| {
+            // Into the instance's space, where its mesh fills the unit shape
+            let basis = instance_basis(instance.position).transpose();
+            let extent = Vec3::new(instance.scale, instance.height, instance.scale);
+            let local_origin = basis * (origin - instance.position) / extent;
+            let local_direction = basis * direction / extent;
+            let t = match cloud.shape {
+                PointShape::Sphere => {
+                    let length = local_direction.length();
+                    let local = Ray {
+                        origin: local_origin,
+                        direction: local_direction / length,
+                    };
+                    ray_sphere_distance(&local, Vec3::ZERO, 1.0).map(|t| t / length)
+                }
+                PointShape::Column => ray_unit_cylinder_distance(local_origin, local_direction),
+            };
+            t.map(|t| (index, t))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Ray through the cursor when the left mouse button is released after a click rather than a
+/// drag, for picking. `press_position` keeps the cursor position between press and release.
+pub fn click_ray(
+    mouse: &Input<MouseButton>,
+    window: &Window,
+    cameras: &Query<(&Camera, &GlobalTransform)>,
+    press_position: &mut Option<Vec2>,
+) -> Option<Ray> {
+    let cursor = window.cursor_position();
+    if mouse.just_pressed(MouseButton::Left) {
+        *press_position = cursor;
+    }
+    if !mouse.just_released(MouseButton::Left) {
+        return None;
+    }
+    let (Some(pressed), Some(cursor)) = (press_position.take(), cursor) else {
+        return None;
+    };
+    if pressed.distance(cursor) > CLICK_TOLERANCE {
+        return None;
+    }
+    cameras
+        .iter()
+        .filter(|(camera, _)| camera.is_active)
+        .find_map(|(camera, transform)| camera.viewport_to_world(transform, cursor))
+}
+
+/// System to send [`PointCloudClicked`] for the nearest point under the cursor on a click.
+///
+/// Releases after dragging the camera are ignored, as are points hidden behind the globe.
+pub fn pick_point_clouds(
+    mouse: Res<Input<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    clouds: Query<(Entity, &PointCloud, &GlobalTransform, &ComputedVisibility)>,
+    mut press_position: Local<Option<Vec2>>,
+    mut events: EventWriter<PointCloudClicked>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    let Some(ray) = click_ray(&mouse, window, &cameras, &mut press_position) else {
+        return;
+    };
+
+    let globe = ray_sphere_distance(&ray, Vec3::ZERO, EARTH_RADIUS).unwrap_or(f32::INFINITY);
+    let hit = clouds
+        .iter()
+        .filter(|(.., visibility)| visibility.is_visible())
+        .filter_map(|(entity, cloud, transform, _)| {
+            pick_instance(&ray, cloud, transform).map(|(index, t)| (entity, index, t))
+        })
+        .filter(|(.., t)| *t <= globe)
+        .min_by(|a, b| a.2.total_cmp(&b.2));
+    if let Some((cloud, index, _)) = hit {
+        events.send(PointCloudClicked { cloud, index });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_nearest_instance() {
+        let cloud = PointCloud {
+            instances: vec![
+                PointInstance::new(Vec3::new(0.0, 0.0, -20.0), 1.0, Color::RED),
+                PointInstance::new(Vec3::new(0.0, 0.5, -10.0), 1.0, Color::RED),
+                PointInstance::new(Vec3::new(5.0, 0.0, -5.0), 1.0, Color::RED),
+            ],
+            ..default()
+        };
+        let ray = Ray {
+            origin: Vec3::ZERO,
+            direction: Vec3::NEG_Z,
+        };
+        let (index, t) = pick_instance(&ray, &cloud, &GlobalTransform::IDENTITY).unwrap();
+        assert_eq!(index, 1);
+        assert!((t - (10.0 - 0.75f32.sqrt())).abs() < 1e-4);
+
+        // Instances are picked in world space
+        let moved = GlobalTransform::from(Transform::from_xyz(-5.0, 0.0, 0.0));
+        assert_eq!(
+            pick_instance(&ray, &cloud, &moved).map(|hit| hit.0),
+            Some(2)
+        );
+
+        let away = Ray {
+            origin: Vec3::ZERO,
+            direction: Vec3::Z,
+        };
+        assert!(pick_instance(&away, &cloud, &GlobalTransform::IDENTITY).is_none());
+
+        let faded = PointCloud {
+            opacity: 0.5,
+            ..cloud
+        };
+        assert!(faded
+            .faded_instances()
+            .iter()
+            .all(|instance| instance.color[3] == 0.5));
+    }
+
+    #[test]
+    fn test_pick_column() {
+        let base = Vec3::new(0.0, 0.0, 100.0);
+        let column = PointInstance::column(base, 1.0, 20.0, Color::RED);
+        assert!(column.base().distance(base) < 1e-4);
+        let cloud = PointCloud {
+            instances: vec![column],
+            shape: PointShape::Column,
+            ..default()
+        };
+        let across = |height: f32| Ray {
+            origin: Vec3::new(-10.0, 0.0, 100.0 + height),
+            direction: Vec3::X,
+        };
+        let (_, t) = pick_instance(&across(15.0), &cloud, &GlobalTransform::IDENTITY).unwrap();
+        assert!((t - 9.0).abs() < 1e-4);
+        assert!(pick_instance(&across(25.0), &cloud, &GlobalTransform::IDENTITY).is_none());
+
+        let down = Ray {
+            origin: Vec3::new(0.5, 0.5, 200.0),
+            direction: Vec3::NEG_Z,
+        };
+        let (_, t) = pick_instance(&down, &cloud, &GlobalTransform::IDENTITY).unwrap();
+        assert!((t - 80.0).abs() < 1e-4);
+    }
+}
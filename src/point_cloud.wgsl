@@ -0,0 +1,56 @@
+#import bevy_pbr::mesh_types
+#import bevy_pbr::mesh_view_bindings
+
+@group(1) @binding(0)
+var<uniform> mesh: Mesh;
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+
+    @location(8) i_position_scale: vec4<f32>,
+    @location(9) i_color: vec4<f32>,
+    @location(10) i_height: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    // Local east, up and north axes, matching `instance_basis`
+    let center = vertex.i_position_scale.xyz;
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if length(center) > 0.0 {
+        up = normalize(center);
+    }
+    var helper = vec3<f32>(1.0, 0.0, 0.0);
+    if abs(up.y) < 0.99 {
+        helper = vec3<f32>(0.0, 1.0, 0.0);
+    }
+    let east = normalize(cross(helper, up));
+    let north = cross(up, east);
+
+    let scale = vertex.i_position_scale.w;
+    let position = center
+        + east * vertex.position.x * scale
+        + up * vertex.position.y * vertex.i_height
+        + north * vertex.position.z * scale;
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(mesh.model, vec4<f32>(position, 1.0));
+    // Faces pointing away from the globe are brightest, so column sides stand apart from
+    // their tops
+    let shade = 0.75 + 0.25 * max(vertex.normal.y, 0.0);
+    out.color = vec4<f32>(vertex.i_color.rgb * shade, vertex.i_color.a);
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,577 @@
+use std::ffi::OsStr;
+
+use bevy::asset::{AssetEvent, AssetLoader, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::view::NoFrustumCulling;
+use bevy::utils::BoxedFuture;
+
+use crate::errors::PointDataError;
+use crate::labels::{LabelSettings, WorldLabel};
+use crate::map::{Coordinates, EARTH_RADIUS, EARTH_RADIUS_KM};
+use crate::point_cloud::{PointCloud, PointCloudClicked, PointInstance, PointShape};
+use crate::scale::{ColorScale, Palette, SizeScale};
+
+/// Tabular point data loaded from a `.csv` file or from the Point features of a `.geojson` file.
+///
+/// Values are kept as text so the same table can be mapped onto markers in different ways.
+/// GeoJSON points get `latitude` and `longitude` columns followed by their properties.
+#[derive(Debug, TypeUuid)]
+#[uuid = "53b6cdc9-e2bd-4776-963c-0699928d282d"]
+pub struct PointTable {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl PointTable {
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(name))
+    }
+
+    /// Parses CSV with a header row. Fields may be quoted, with `""` escaping a quote.
+    pub fn from_csv(text: &str) -> Result<Self, PointDataError> {
+        let mut records = parse_csv_records(text)?.into_iter();
+        let columns = records.next().ok_or(PointDataError {
+            msg: "CSV file is empty".to_string(),
+        })?;
+        let rows = records
+            .filter(|row| !(row.len() == 1 && row[0].is_empty()))
+            .collect();
+        Ok(Self { columns, rows })
+    }
+
+    /// Reads the Point and MultiPoint features of a GeoJSON FeatureCollection, Feature or
+    /// bare geometry
+    #[cfg(feature = "geojson")]
+    pub fn from_geojson(text: &str) -> Result<Self, PointDataError> {
+        use serde_json::Value;
+
+        let root: Value = serde_json::from_str(text).map_err(|err| PointDataError {
+            msg: format!("Invalid GeoJSON: {err}"),
+        })?;
+        let features = match root["type"].as_str() {
+            Some("FeatureCollection") => root["features"].as_array().cloned().unwrap_or_default(),
+            Some("Feature") => vec![root],
+            Some(_) => vec![serde_json::json!({ "geometry": root })],
+            None => {
+                return Err(PointDataError {
+                    msg: "GeoJSON object has no type".to_string(),
+                })
+            }
+        };
+
+        let mut columns = vec!["latitude".to_string(), "longitude".to_string()];
+        let mut points = Vec::new();
+        for feature in &features {
+            let geometry = &feature["geometry"];
+            let coordinates = match geometry["type"].as_str() {
+                Some("Point") => vec![&geometry["coordinates"]],
+                Some("MultiPoint") => geometry["coordinates"]
+                    .as_array()
+                    .map(|points| points.iter().collect())
+                    .unwrap_or_default(),
+                _ => continue,
+            };
+            let properties = feature["properties"].as_object();
+            for property in properties.into_iter().flat_map(|p| p.keys()) {
+                if !columns.contains(property) {
+                    columns.push(property.clone());
+                }
+            }
+            for position in coordinates {
+                // GeoJSON positions are [longitude, latitude]
+                if let (Some(lon), Some(lat)) = (position[0].as_f64(), position[1].as_f64()) {
+                    points.push((lat, lon, properties));
+                }
+            }
+        }
+
+        let rows = points
+            .into_iter()
+            .map(|(lat, lon, properties)| {
+                let mut row = vec![lat.to_string(), lon.to_string()];
+                for column in &columns[2..] {
+                    let value = properties.and_then(|p| p.get(column));
+                    row.push(match value {
+                        Some(Value::String(text)) => text.clone(),
+                        Some(Value::Null) | None => String::new(),
+                        Some(value) => value.to_string(),
+                    });
+                }
+                row
+            })
+            .collect();
+        Ok(Self { columns, rows })
+    }
+}
+
+fn parse_csv_records(text: &str) -> Result<Vec<Vec<String>>, PointDataError> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() => in_quotes = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(PointDataError {
+            msg: "CSV file ends inside a quoted field".to_string(),
+        });
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[derive(Default)]
+pub struct PointTableLoader;
+
+impl AssetLoader for PointTableLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let text = std::str::from_utf8(bytes)?;
+            let is_csv = load_context.path().extension() == Some(OsStr::new("csv"));
+            let table = if is_csv {
+                PointTable::from_csv(text)?
+            } else {
+                #[cfg(feature = "geojson")]
+                {
+                    PointTable::from_geojson(text)?
+                }
+                #[cfg(not(feature = "geojson"))]
+                {
+                    return Err(bevy::asset::Error::msg(
+                        "Loading GeoJSON points requires the `geojson` feature",
+                    ));
+                }
+            };
+            load_context.set_default_asset(LoadedAsset::new(table));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["csv", "geojson"]
+    }
+}
+
+/// How each marker of a [`PointLayer`] is drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MarkerStyle {
+    /// A sphere centred on the surface, with its radius from the size scale
+    #[default]
+    Sphere,
+    /// A column standing on the surface along the local up vector, with its height from the
+    /// size scale and a base `footprint_km` across
+    Column { footprint_km: f32 },
+}
+
+/// Spawns a marker for every row of a [`PointTable`], sized and colored by a value column.
+///
+/// Markers are drawn by an instanced child of the layer's entity, spawned once the table has
+/// loaded and respawned if the file changes.
+#[derive(Component)]
+pub struct PointLayer {
+    pub source: Handle<PointTable>,
+    pub latitude_column: String,
+    pub longitude_column: String,
+    pub value_column: Option<String>,
+    pub label_column: Option<String>,
+    pub size_scale: SizeScale,
+    pub color_scale: ColorScale,
+    pub marker_style: MarkerStyle,
+    /// Markers closer together than this many pixels on screen are merged into one cluster
+    pub cluster_radius: Option<f32>,
+}
+
+impl PointLayer {
+    pub fn new(source: Handle<PointTable>) -> Self {
+        Self {
+            source,
+            latitude_column: "latitude".to_string(),
+            longitude_column: "longitude".to_string(),
+            value_column: None,
+            label_column: None,
+            size_scale: SizeScale::new(2.0, 7.0),
+            color_scale: ColorScale::new(Palette::YellowRed),
+            marker_style: MarkerStyle::Sphere,
+            cluster_radius: None,
+        }
+    }
+
+    pub fn with_position_columns(mut self, latitude: &str, longitude: &str) -> Self {
+        self.latitude_column = latitude.to_string();
+        self.longitude_column = longitude.to_string();
+        self
+    }
+
+    pub fn with_value_column(mut self, column: &str) -> Self {
+        self.value_column = Some(column.to_string());
+        self
+    }
+
+    pub fn with_label_column(mut self, column: &str) -> Self {
+        self.label_column = Some(column.to_string());
+        self
+    }
+
+    /// Values mapped to the smallest and largest markers and the ends of the color scale.
+    /// Without one, both scales fit the data's range.
+    pub fn with_value_range(mut self, min: f32, max: f32) -> Self {
+        self.size_scale.domain = Some((min, max));
+        self.color_scale.domain = Some((min, max));
+        self
+    }
+
+    pub fn with_radius(mut self, min: f32, max: f32) -> Self {
+        self.size_scale.range = (min, max);
+        self
+    }
+
+    pub fn with_colors(mut self, low: Color, high: Color) -> Self {
+        self.color_scale.colors = vec![low, high];
+        self
+    }
+
+    pub fn with_size_scale(mut self, scale: SizeScale) -> Self {
+        self.size_scale = scale;
+        self
+    }
+
+    pub fn with_color_scale(mut self, scale: ColorScale) -> Self {
+        self.color_scale = scale;
+        self
+    }
+
+    /// Draws markers as columns with a base `footprint_km` across, rising up to `max_height`
+    /// world units. Unless the layer has a value range, heights are proportional to the
+    /// values.
+    pub fn with_columns(mut self, footprint_km: f32, max_height: f32) -> Self {
+        self.marker_style = MarkerStyle::Column { footprint_km };
+        self.size_scale.range = (0.0, max_height);
+        self
+    }
+
+    pub fn with_clustering(mut self, radius: f32) -> Self {
+        self.cluster_radius = Some(radius);
+        self
+    }
+}
+
+/// A single point of a layer, carrying the row it came from
+#[derive(Debug, Clone)]
+pub struct PointMarker {
+    pub row: usize,
+    pub coordinates: Coordinates,
+    pub label: Option<String>,
+    pub value: Option<f32>,
+}
+
+/// The markers of a layer, in the same order as the instances of the [`PointCloud`] on the
+/// same entity
+#[derive(Component, Debug)]
+pub struct PointMarkers {
+    pub layer: Entity,
+    pub markers: Vec<PointMarker>,
+}
+
+/// Links a [`WorldLabel`] to its marker's index in the owning [`PointMarkers`]
+#[derive(Component, Debug)]
+pub struct PointLabel {
+    pub index: usize,
+}
+
+/// Added to a [`PointLayer`] entity once its markers have been spawned
+#[derive(Component)]
+pub struct PointLayerSpawned;
+
+/// Reads a marker from every row of a table with a valid position, skipping the others with
+/// a warning. Values that aren't numbers are left out.
+pub fn read_markers(
+    table: &PointTable,
+    latitude_column: &str,
+    longitude_column: &str,
+    value_column: Option<&str>,
+    label_column: Option<&str>,
+) -> Result<Vec<PointMarker>, PointDataError> {
+    let find = |name: &str| {
+        table.column(name).ok_or_else(|| PointDataError {
+            msg: format!("Point data has no `{name}` column"),
+        })
+    };
+    let lat_column = find(latitude_column)?;
+    let lon_column = find(longitude_column)?;
+    let value_column = value_column.map(find).transpose()?;
+    let label_column = label_column.map(find).transpose()?;
+
+    let mut markers = Vec::new();
+    for (row, fields) in table.rows.iter().enumerate() {
+        let field = |column: usize| fields.get(column).map(|field| field.trim());
+        let latitude = field(lat_column).and_then(|field| field.parse().ok());
+        let longitude = field(lon_column).and_then(|field| field.parse().ok());
+        let coordinates = match (latitude, longitude) {
+            (Some(latitude), Some(longitude)) => Coordinates::from_degrees(latitude, longitude),
+            _ => {
+                warn!("Skipping point row {row}: missing or invalid position");
+                continue;
+            }
+        };
+        let coordinates = match coordinates {
+            Ok(coordinates) => coordinates,
+            Err(err) => {
+                warn!("Skipping point row {row}: {err}");
+                continue;
+            }
+        };
+        markers.push(PointMarker {
+            row,
+            coordinates,
+            label: label_column.and_then(field).map(|label| label.to_string()),
+            value: value_column
+                .and_then(field)
+                .and_then(|value| value.parse().ok()),
+        });
+    }
+    Ok(markers)
+}
+
+/// System to spawn markers for point layers whose data has loaded.
+///
+/// All markers of a layer are drawn by a single [`PointCloud`] child entity, which also holds
+/// their [`PointMarkers`].
+pub fn spawn_point_layers(
+    mut commands: Commands,
+    layers: Query<(Entity, &PointLayer), Without<PointLayerSpawned>>,
+    tables: Res<Assets<PointTable>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut sphere_mesh: Local<Option<Handle<Mesh>>>,
+    mut column_mesh: Local<Option<Handle<Mesh>>>,
+) {
+    for (entity, layer) in layers.iter() {
+        let Some(table) = tables.get(&layer.source) else {
+            continue;
+        };
+        commands.entity(entity).insert(PointLayerSpawned);
+        let markers = read_markers(
+            table,
+            &layer.latitude_column,
+            &layer.longitude_column,
+            layer.value_column.as_deref(),
+            layer.label_column.as_deref(),
+        );
+        let markers = match markers {
+            Ok(markers) => markers,
+            Err(err) => {
+                error!("Failed to read point layer: {err}");
+                continue;
+            }
+        };
+
+        // Create meshes that will be reused for all markers. They are drawn once per point, so
+        // they are kept coarse.
+        let (mesh, shape) = match layer.marker_style {
+            MarkerStyle::Sphere => {
+                let mesh = sphere_mesh.get_or_insert_with(|| {
+                    meshes.add(
+                        Mesh::try_from(shape::Icosphere {
+                            radius: 1.0, // Scaled per instance
+                            subdivisions: 3,
+                        })
+                        .unwrap(),
+                    )
+                });
+                (mesh.clone(), PointShape::Sphere)
+            }
+            MarkerStyle::Column { .. } => {
+                let mesh = column_mesh.get_or_insert_with(|| {
+                    meshes.add(Mesh::from(shape::Cylinder {
+                        radius: 1.0, // Scaled per instance
+                        height: 2.0,
+                        resolution: 12,
+                        segments: 1,
+                    }))
+                });
+                (mesh.clone(), PointShape::Column)
+            }
+        };
+
+        let values = || markers.iter().filter_map(|marker| marker.value);
+        let size_scale = match layer.marker_style {
+            // Fitting from zero keeps column heights proportional to the values
+            MarkerStyle::Column { .. } => layer.size_scale.fit(values().chain([0.0])),
+            MarkerStyle::Sphere => layer.size_scale.fit(values()),
+        };
+        let color_scale = layer.color_scale.fit(values());
+
+        let instances = markers
+            .iter()
+            .map(|marker| {
+                let coords = marker.coordinates.get_point_on_sphere();
+                let (size, color) = match marker.value {
+                    Some(value) => (size_scale.size(value), color_scale.color(value)),
+                    None => (size_scale.range.0, color_scale.sample(0.0)),
+                };
+                match layer.marker_style {
+                    MarkerStyle::Sphere => PointInstance::new(coords, size, color),
+                    MarkerStyle::Column { footprint_km } => {
+                        let radius = footprint_km / 2.0 / EARTH_RADIUS_KM * EARTH_RADIUS;
+                        PointInstance::column(coords, radius, size, color)
+                    }
+                }
+            })
+            .collect();
+
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn((
+                mesh,
+                SpatialBundle::INHERITED_IDENTITY,
+                NoFrustumCulling,
+                PointCloud {
+                    instances,
+                    shape,
+                    ..default()
+                },
+                PointMarkers {
+                    layer: entity,
+                    markers,
+                },
+            ));
+        });
+    }
+}
+
+/// System to spawn a [`WorldLabel`] for every labelled marker of a newly spawned layer,
+/// prioritized by the marker's value
+pub fn spawn_point_labels(
+    mut commands: Commands,
+    settings: Res<LabelSettings>,
+    asset_server: Res<AssetServer>,
+    point_markers: Query<(Entity, &PointMarkers, &PointCloud), Added<PointMarkers>>,
+) {
+    for (entity, point_markers, cloud) in point_markers.iter() {
+        let font = asset_server.load(settings.font_path.as_str());
+        for (index, marker) in point_markers.markers.iter().enumerate() {
+            let Some(label) = &marker.label else {
+                continue;
+            };
+            // Above the marker, so labels clear tall columns
+            let anchor = cloud.instances.get(index).map_or_else(
+                || marker.coordinates.get_point_on_sphere(),
+                |instance| instance.position + instance.position.normalize() * instance.height,
+            );
+            commands.spawn((
+                WorldLabel::text_bundle(label, font.clone(), &settings),
+                WorldLabel {
+                    anchor,
+                    priority: marker.value.unwrap_or(0.0),
+                    owner: entity,
+                    hidden: false,
+                },
+                PointLabel { index },
+            ));
+        }
+    }
+}
+
+/// System to log the marker behind each click on a point layer
+pub fn log_point_clicks(
+    mut clicks: EventReader<PointCloudClicked>,
+    point_markers: Query<&PointMarkers>,
+) {
+    for click in clicks.iter() {
+        let Ok(point_markers) = point_markers.get(click.cloud) else {
+            continue;
+        };
+        let Some(marker) = point_markers.markers.get(click.index) else {
+            continue;
+        };
+        let (latitude, longitude) = marker.coordinates.as_degrees();
+        info!(
+            "Clicked point {} (row {}) at Lat: {}, Lon: {}",
+            marker.label.as_deref().unwrap_or("without label"),
+            marker.row,
+            latitude,
+            longitude
+        );
+    }
+}
+
+/// System to respawn a layer's markers when its data file is reloaded
+pub fn reload_point_layers(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<PointTable>>,
+    layers: Query<(Entity, &PointLayer), With<PointLayerSpawned>>,
+) {
+    for event in events.iter() {
+        let AssetEvent::Modified { handle } = event else {
+            continue;
+        };
+        for (entity, layer) in layers.iter() {
+            if &layer.source == handle {
+                let mut layer_commands = commands.entity(entity);
+                layer_commands.despawn_descendants();
+                layer_commands.remove::<PointLayerSpawned>();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_parsing() {
+        let table = PointTable::from_csv(
+            "name,latitude,longitude\r\n\"Washington, D.C.\",38.9,-77.0\r\n\"The \"\"Big\"\" Apple\",40.7,-74.0\n",
+        )
+        .unwrap();
+        assert_eq!(table.columns, vec!["name", "latitude", "longitude"]);
+        assert_eq!(table.rows.len(), 2);
+        assert_eq!(table.rows[0], vec!["Washington, D.C.", "38.9", "-77.0"]);
+        assert_eq!(table.rows[1][0], "The \"Big\" Apple");
+        assert_eq!(table.column("Latitude"), Some(1));
+    }
+
+    #[cfg(feature = "geojson")]
+    #[test]
+    fn test_geojson_parsing() {
+        let table = PointTable::from_geojson(
+            r#"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "geometry": {"type": "Point", "coordinates": [139.65, 35.68]},
+                 "properties": {"name": "Tokyo", "population": 37.4}},
+                {"type": "Feature", "geometry": {"type": "LineString", "coordinates": []},
+                 "properties": {"name": "Ignored"}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            table.columns,
+            vec!["latitude", "longitude", "name", "population"]
+        );
+        assert_eq!(table.rows, vec![vec!["35.68", "139.65", "Tokyo", "37.4"]]);
+    }
+}
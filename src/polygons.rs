@@ -0,0 +1,627 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+#[cfg(feature = "vector-gdal")]
+use triangulate::{formats, ListFormat, PolygonList};
+
+#[cfg(feature = "vector-gdal")]
+use gdal::errors::GdalError;
+
+use crate::map::{open_height_source, Coordinates, HeightSource, TerrainSettings, EARTH_RADIUS};
+
+/// Points closer than this on the unit sphere are treated as duplicates
+const DUPLICATE_DISTANCE: f32 = 1e-6;
+
+/// Triangles spanning less volume with the globe's center than this fraction of their
+/// longest edge squared are treated as degenerate
+#[cfg(feature = "vector-gdal")]
+const DEGENERATE_RATIO: f32 = 1e-5;
+
+/// Polygon vertices must be at least this far from the horizon of the gnomonic projection,
+/// as the cosine of their angle from its center, to be triangulated in it
+#[cfg(feature = "vector-gdal")]
+const MIN_GNOMONIC_COSINE: f32 = 0.1;
+
+/// A polygon with an exterior ring and any number of holes. Rings are not closed: the last
+/// point connects back to the first.
+#[derive(Debug, Clone, Default)]
+pub struct GeoPolygon {
+    pub exterior: Vec<Coordinates>,
+    pub holes: Vec<Vec<Coordinates>>,
+}
+
+/// A feature read from a vector file, with its attributes as text
+#[derive(Debug, Clone, Default)]
+pub struct PolygonFeature {
+    pub polygons: Vec<GeoPolygon>,
+    pub properties: HashMap<String, String>,
+}
+
+/// Reads the Polygon and MultiPolygon features of every layer in a vector file GDAL can open,
+/// such as GeoJSON or a Shapefile.
+///
+/// Coordinates are taken as longitude and latitude in degrees, as in GeoJSON.
+#[cfg(feature = "vector-gdal")]
+pub fn read_polygon_features(path: &str) -> Result<Vec<PolygonFeature>, GdalError> {
+    use gdal::vector::{LayerAccess, OGRwkbGeometryType};
+    use gdal::Dataset;
+
+    let dataset = Dataset::open(path)?;
+    let mut features = Vec::new();
+    for mut layer in dataset.layers() {
+        for feature in layer.features() {
+            let Some(geometry) = feature.geometry() else {
+                continue;
+            };
+            let polygons = match geometry.geometry_type() {
+                OGRwkbGeometryType::wkbPolygon | OGRwkbGeometryType::wkbPolygon25D => {
+                    vec![read_polygon(geometry)]
+                }
+                OGRwkbGeometryType::wkbMultiPolygon | OGRwkbGeometryType::wkbMultiPolygon25D => {
+                    let count = geometry.geometry_count();
+                    (0..count)
+                        .map(|index| read_polygon(&geometry.get_geometry(index)))
+                        .collect()
+                }
+                _ => continue,
+            };
+            let properties = feature
+                .fields()
+                .filter_map(|(name, value)| Some((name, value?.into_string()?)))
+                .collect();
+            features.push(PolygonFeature {
+                polygons,
+                properties,
+            });
+        }
+    }
+    Ok(features)
+}
+
+#[cfg(feature = "vector-gdal")]
+fn read_polygon(geometry: &gdal::vector::Geometry) -> GeoPolygon {
+    let mut rings = (0..geometry.geometry_count()).map(|index| {
+        geometry
+            .get_geometry(index)
+            .get_point_vec()
+            .into_iter()
+            .filter_map(|(lon, lat, _)| Coordinates::from_degrees(lat as f32, lon as f32).ok())
+            .collect::<Vec<_>>()
+    });
+    GeoPolygon {
+        exterior: rings.next().unwrap_or_default(),
+        holes: rings.collect(),
+    }
+}
+
+/// Unit vectors for a ring, without repeated points or the closing point GeoJSON and
+/// Shapefiles repeat at the end
+fn ring_directions(ring: &[Coordinates]) -> Vec<Vec3> {
+    let mut directions: Vec<Vec3> = Vec::with_capacity(ring.len());
+    for coordinates in ring {
+        let direction = coordinates.get_point_on_sphere().normalize();
+        let duplicate = directions
+            .last()
+            .is_some_and(|last| last.distance(direction) <= DUPLICATE_DISTANCE);
+        if !duplicate {
+            directions.push(direction);
+        }
+    }
+    while directions.len() > 1
+        && directions[0].distance(directions[directions.len() - 1]) <= DUPLICATE_DISTANCE
+    {
+        directions.pop();
+    }
+    directions
+}
+
+fn slerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    let angle = a.angle_between(b);
+    if angle < 1e-6 {
+        return a.lerp(b, t).normalize();
+    }
+    ((a * ((1.0 - t) * angle).sin() + b * (t * angle).sin()) / angle.sin()).normalize()
+}
+
+/// Adds points along the great circle between consecutive ring points, so no edge spans
+/// more than `max_angle` radians
+pub fn subdivide_ring(ring: &[Vec3], max_angle: f32) -> Vec<Vec3> {
+    let mut subdivided = Vec::with_capacity(ring.len());
+    for (index, &start) in ring.iter().enumerate() {
+        let end = ring[(index + 1) % ring.len()];
+        let steps = (start.angle_between(end) / max_angle).ceil().max(1.0) as usize;
+        subdivided.push(start);
+        for step in 1..steps {
+            subdivided.push(slerp(start, end, step as f32 / steps as f32));
+        }
+    }
+    subdivided
+}
+
+/// Projects rings onto a plane for triangulation, about the center of the exterior ring.
+///
+/// The gnomonic projection maps great circles to straight lines, so the subdivided edges stay
+/// straight and the triangulation matches the polygon on the sphere. Polygons too large for
+/// it fall back to the azimuthal equidistant projection, which bends the short subdivided
+/// edges only slightly and, unlike longitude and latitude, has no seam at the antimeridian or
+/// the poles. Only the point opposite the center can't be projected.
+#[cfg(feature = "vector-gdal")]
+fn project_rings(rings: &[Vec<Vec3>]) -> Vec<Vec<[f32; 2]>> {
+    let sum = rings[0].iter().copied().sum::<Vec3>();
+    // A ring balanced around the globe's center, like one along the equator, has no mean
+    // direction, but any of its points still works as a center
+    let center = sum.try_normalize().unwrap_or(rings[0][0]);
+    let gnomonic = rings
+        .iter()
+        .flatten()
+        .all(|direction| direction.dot(center) > MIN_GNOMONIC_COSINE);
+    let east = center.any_orthonormal_vector();
+    let north = center.cross(east);
+    rings
+        .iter()
+        .map(|ring| {
+            ring.iter()
+                .map(|&direction| {
+                    let projected = if gnomonic {
+                        direction / direction.dot(center)
+                    } else {
+                        // Tangent direction scaled to the angle from the center
+                        let tangent = direction - center * direction.dot(center);
+                        tangent.normalize_or_zero() * direction.angle_between(center)
+                    };
+                    [projected.dot(east), projected.dot(north)]
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Splits every edge longer than `max_chord` at its great-circle midpoint until none are
+/// left. Splitting is decided per edge, so triangles sharing an edge split it the same way and
+/// the surface stays free of cracks.
+pub fn refine_triangles(
+    directions: &mut Vec<Vec3>,
+    mut triangles: Vec<[u32; 3]>,
+    max_chord: f32,
+) -> Vec<[u32; 3]> {
+    loop {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32, directions: &mut Vec<Vec3>| {
+            if directions[a as usize].distance(directions[b as usize]) <= max_chord {
+                return None;
+            }
+            let key = (a.min(b), a.max(b));
+            Some(*midpoints.entry(key).or_insert_with(|| {
+                let middle = (directions[a as usize] + directions[b as usize]).normalize();
+                directions.push(middle);
+                directions.len() as u32 - 1
+            }))
+        };
+
+        let mut refined = Vec::with_capacity(triangles.len());
+        for v in &triangles {
+            let m = [
+                midpoint(v[0], v[1], directions),
+                midpoint(v[1], v[2], directions),
+                midpoint(v[2], v[0], directions),
+            ];
+            match m {
+                [None, None, None] => refined.push(*v),
+                [Some(ab), Some(bc), Some(ca)] => {
+                    refined.extend([[v[0], ab, ca], [ab, v[1], bc], [ca, bc, v[2]], [ab, bc, ca]])
+                }
+                _ => {
+                    let split = m.iter().filter(|m| m.is_some()).count();
+                    // Rotate so the pattern starts at edge k: the only split edge when one
+                    // is split, or the only unsplit edge when two are
+                    let k = (0..3).find(|&k| m[k].is_some() == (split == 1)).unwrap();
+                    let (a, b, c) = (v[k], v[(k + 1) % 3], v[(k + 2) % 3]);
+                    if split == 1 {
+                        let ab = m[k].unwrap();
+                        refined.extend([[a, ab, c], [ab, b, c]]);
+                    } else {
+                        let bc = m[(k + 1) % 3].unwrap();
+                        let ca = m[(k + 2) % 3].unwrap();
+                        refined.extend([[bc, c, ca], [a, b, bc], [a, bc, ca]]);
+                    }
+                }
+            }
+        }
+        if refined.len() == triangles.len() {
+            return refined;
+        }
+        triangles = refined;
+    }
+}
+
+/// Triangulates a polygon on the unit sphere, with edges no longer than `max_angle` radians.
+///
+/// Returns the vertex directions and counter-clockwise (outward facing) triangles, or `None`
+/// if the polygon is degenerate or can't be triangulated.
+#[cfg(feature = "vector-gdal")]
+pub fn triangulate_polygon(
+    polygon: &GeoPolygon,
+    max_angle: f32,
+) -> Option<(Vec<Vec3>, Vec<[u32; 3]>)> {
+    let exterior = ring_directions(&polygon.exterior);
+    if exterior.len() < 3 {
+        return None;
+    }
+    let holes = polygon
+        .holes
+        .iter()
+        .map(|ring| ring_directions(ring))
+        .filter(|ring| ring.len() >= 3);
+    let rings: Vec<Vec<Vec3>> = std::iter::once(exterior)
+        .chain(holes)
+        .map(|ring| subdivide_ring(&ring, max_angle))
+        .collect();
+
+    let projected = project_rings(&rings);
+    let mut triangulated: Vec<[usize; 2]> = Vec::new();
+    projected
+        .triangulate(formats::IndexedListFormat::new(&mut triangulated).into_fan_format())
+        .ok()?;
+
+    let mut offsets = Vec::with_capacity(rings.len());
+    let mut directions = Vec::new();
+    for ring in &rings {
+        offsets.push(directions.len() as u32);
+        directions.extend_from_slice(ring);
+    }
+    let triangles = triangulated
+        .chunks_exact(3)
+        .filter_map(|triangle| {
+            // Points along one subdivided edge lie on a great circle, and the triangulation
+            // may join them into empty slivers. They are only nearly collinear in the
+            // equidistant projection, so look for them on the sphere.
+            let [pa, pb, pc] = [0, 1, 2].map(|i| rings[triangle[i][0]][triangle[i][1]]);
+            let volume = pa.dot(pb.cross(pc));
+            let longest = pa
+                .distance_squared(pb)
+                .max(pb.distance_squared(pc))
+                .max(pc.distance_squared(pa));
+            if volume.abs() <= DEGENERATE_RATIO * longest {
+                return None;
+            }
+            let [a, b, c] = [0, 1, 2].map(|i| offsets[triangle[i][0]] + triangle[i][1] as u32);
+            // Wind the triangles counter-clockwise seen from outside the globe
+            Some(if volume > 0.0 { [a, b, c] } else { [a, c, b] })
+        })
+        .collect();
+
+    let max_chord = 2.0 * (max_angle / 2.0).sin();
+    let triangles = refine_triangles(&mut directions, triangles, max_chord);
+    Some((directions, triangles))
+}
+
+/// Position of a direction on the terrain, raised by `lift` world units
+fn surface_point(
+    direction: Vec3,
+    lift: f32,
+    exaggeration: f64,
+    rs: &(impl HeightSource + ?Sized),
+) -> Vec3 {
+    let (latitude, longitude) = Coordinates::from(direction).as_degrees();
+    let height = rs
+        .height_at(latitude as f64, longitude as f64)
+        .map_or(0.0, |height| height.max(0.0) * exaggeration);
+    direction * (EARTH_RADIUS + height as f32 + lift)
+}
+
+/// Fill mesh for all the polygons of a feature, draped over the terrain
+#[cfg(feature = "vector-gdal")]
+pub fn polygon_fill_mesh(
+    polygons: &[GeoPolygon],
+    max_angle: f32,
+    lift: f32,
+    exaggeration: f64,
+    rs: &(impl HeightSource + ?Sized),
+) -> Option<Mesh> {
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut indices = Vec::new();
+    for polygon in polygons {
+        let Some((directions, triangles)) = triangulate_polygon(polygon, max_angle) else {
+            continue;
+        };
+        let offset = positions.len() as u32;
+        for direction in directions {
+            positions.push(surface_point(direction, lift, exaggeration, rs));
+            normals.push(direction);
+        }
+        indices.extend(triangles.into_iter().flatten().map(|index| index + offset));
+    }
+    if indices.is_empty() {
+        return None;
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    Some(mesh)
+}
+
+/// Line mesh tracing every ring of the features along great circles
+pub fn polygon_outline_mesh(
+    features: &[PolygonFeature],
+    max_angle: f32,
+    lift: f32,
+    exaggeration: f64,
+    rs: &(impl HeightSource + ?Sized),
+) -> Mesh {
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut indices = Vec::new();
+    let rings = features
+        .iter()
+        .flat_map(|feature| &feature.polygons)
+        .flat_map(|polygon| std::iter::once(&polygon.exterior).chain(&polygon.holes));
+    for ring in rings {
+        let ring = ring_directions(ring);
+        if ring.len() < 2 {
+            continue;
+        }
+        let ring = subdivide_ring(&ring, max_angle);
+        let offset = positions.len() as u32;
+        for (index, &direction) in ring.iter().enumerate() {
+            positions.push(surface_point(direction, lift, exaggeration, rs));
+            normals.push(direction);
+            let next = (index + 1) % ring.len();
+            indices.extend([offset + index as u32, offset + next as u32]);
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh
+}
+
+/// Draws the polygons of a vector file, such as country borders, over the terrain.
+///
+/// Each feature becomes a child entity with its own fill material and a [`GeoFeature`], and
+/// all outlines share one more child.
+#[derive(Component)]
+pub struct PolygonLayer {
+    /// A GeoJSON file, Shapefile or anything else GDAL reads as vector data
+    pub path: String,
+    pub fill_color: Color,
+    pub outline_color: Color,
+    /// Longest edge, in degrees, before it is subdivided to follow the globe
+    pub max_edge: f32,
+    /// World units to raise fills and outlines above the terrain
+    pub lift: f32,
+}
+
+impl PolygonLayer {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            fill_color: Color::rgba(0.2, 0.5, 0.9, 0.4),
+            outline_color: Color::WHITE,
+            max_edge: 1.0,
+            lift: 1.0,
+        }
+    }
+
+    pub fn with_fill_color(mut self, color: Color) -> Self {
+        self.fill_color = color;
+        self
+    }
+
+    pub fn with_outline_color(mut self, color: Color) -> Self {
+        self.outline_color = color;
+        self
+    }
+
+    pub fn with_max_edge(mut self, degrees: f32) -> Self {
+        self.max_edge = degrees;
+        self
+    }
+
+    pub fn with_lift(mut self, lift: f32) -> Self {
+        self.lift = lift;
+        self
+    }
+}
+
+/// A feature of a [`PolygonLayer`], on the entity drawing its fill
+#[derive(Component, Debug)]
+pub struct GeoFeature {
+    pub layer: Entity,
+    /// Position of the feature in the file
+    pub index: usize,
+    pub properties: HashMap<String, String>,
+}
+
+/// Marks the entity drawing a [`PolygonLayer`]'s outlines
+#[derive(Component)]
+pub struct PolygonOutline;
+
+fn load_features(path: &str) -> Option<Vec<PolygonFeature>> {
+    #[cfg(feature = "vector-gdal")]
+    match read_polygon_features(path) {
+        Ok(features) => Some(features),
+        Err(err) => {
+            error!("Failed to read polygon layer {path}: {err}");
+            None
+        }
+    }
+    #[cfg(not(feature = "vector-gdal"))]
+    {
+        error!("Reading polygon layer {path} requires the `vector-gdal` feature");
+        None
+    }
+}
+
+/// System to read and spawn newly added polygon layers
+pub fn spawn_polygon_layers(
+    mut commands: Commands,
+    layers: Query<(Entity, &PolygonLayer), Added<PolygonLayer>>,
+    settings: Res<TerrainSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // Opened once for all the layers added this frame, and only when there are any
+    let mut rs = None;
+    for (entity, layer) in layers.iter() {
+        let Some(features) = load_features(&layer.path) else {
+            continue;
+        };
+
+        let rs = rs.get_or_insert_with(|| open_height_source(&settings.elevation_path));
+        let max_angle = layer.max_edge.to_radians();
+        commands.entity(entity).with_children(|parent| {
+            // Fills need the triangulation that comes with `vector-gdal`, without which there
+            // are no features to fill anyway
+            #[cfg(feature = "vector-gdal")]
+            for (index, feature) in features.iter().enumerate() {
+                let Some(mesh) = polygon_fill_mesh(
+                    &feature.polygons,
+                    max_angle,
+                    layer.lift,
+                    settings.exaggeration,
+                    rs.as_ref(),
+                ) else {
+                    continue;
+                };
+                parent.spawn((
+                    PbrBundle {
+                        mesh: meshes.add(mesh),
+                        material: materials.add(StandardMaterial {
+                            base_color: layer.fill_color,
+                            alpha_mode: AlphaMode::Blend,
+                            unlit: true,
+                            ..default()
+                        }),
+                        ..default()
+                    },
+                    GeoFeature {
+                        layer: entity,
+                        index,
+                        properties: feature.properties.clone(),
+                    },
+                ));
+            }
+
+            parent.spawn((
+                PbrBundle {
+                    mesh: meshes.add(polygon_outline_mesh(
+                        &features,
+                        max_angle,
+                        layer.lift,
+                        settings.exaggeration,
+                        rs.as_ref(),
+                    )),
+                    material: materials.add(StandardMaterial {
+                        base_color: layer.outline_color,
+                        unlit: true,
+                        // Keep outlines in front of the fills they border
+                        depth_bias: 10.0,
+                        ..default()
+                    }),
+                    ..default()
+                },
+                PolygonOutline,
+            ));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring(points: &[(f32, f32)]) -> Vec<Coordinates> {
+        points
+            .iter()
+            .map(|&(lat, lon)| Coordinates::from_degrees(lat, lon).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_subdivide_ring_follows_great_circles() {
+        let ring = ring_directions(&ring(&[(0.0, 0.0), (0.0, 10.0), (10.0, 0.0), (0.0, 0.0)]));
+        assert_eq!(ring.len(), 3);
+        let subdivided = subdivide_ring(&ring, 1f32.to_radians());
+        for (index, point) in subdivided.iter().enumerate() {
+            let next = subdivided[(index + 1) % subdivided.len()];
+            assert!(point.angle_between(next) <= 1f32.to_radians() + 1e-5);
+            assert!((point.length() - 1.0).abs() < 1e-5);
+        }
+        // The first edge runs along the equator
+        assert!(subdivided[..10].iter().all(|point| point.y.abs() < 1e-5));
+    }
+
+    #[test]
+    #[cfg(feature = "vector-gdal")]
+    fn test_polygon_fill_is_draped_and_refined() {
+        use crate::map::FlatSurface;
+
+        let polygon = GeoPolygon {
+            exterior: ring(&[(-10.0, -10.0), (-10.0, 10.0), (10.0, 10.0), (10.0, -10.0)]),
+            holes: Vec::new(),
+        };
+        let max_angle = 2f32.to_radians();
+        let (directions, triangles) = triangulate_polygon(&polygon, max_angle).unwrap();
+        let max_chord = 2.0 * (max_angle / 2.0).sin();
+
+        let mut area = 0.0;
+        for triangle in &triangles {
+            let [a, b, c] = triangle.map(|index| directions[index as usize]);
+            let normal = (b - a).cross(c - a);
+            assert!(normal.dot(a + b + c) > 0.0, "triangle faces inward");
+            for (start, end) in [(a, b), (b, c), (c, a)] {
+                assert!(start.distance(end) <= max_chord + 1e-5);
+            }
+            area += normal.length() / 2.0;
+        }
+        // Steradians covered by the 20 by 20 degree box
+        let expected = 20f32.to_radians() * 2.0 * 10f32.to_radians().sin();
+        assert!((area - expected).abs() / expected < 0.01);
+
+        let mesh = polygon_fill_mesh(&[polygon], max_angle, 1.0, 1.0, &FlatSurface).unwrap();
+        let Some(bevy::render::mesh::VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("fill mesh has no positions");
+        };
+        for position in positions {
+            assert!((Vec3::from(*position).length() - (EARTH_RADIUS + 1.0)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "vector-gdal")]
+    fn test_polygon_fill_around_pole() {
+        // Too close to the equator for the gnomonic projection, and crossing the antimeridian
+        let points: Vec<(f32, f32)> = (0..8).map(|i| (5.0, 135.0 - 45.0 * i as f32)).collect();
+        let polygon = GeoPolygon {
+            exterior: ring(&points),
+            holes: Vec::new(),
+        };
+        let (directions, triangles) = triangulate_polygon(&polygon, 2f32.to_radians()).unwrap();
+
+        let mut area = 0.0;
+        for triangle in &triangles {
+            let [a, b, c] = triangle.map(|index| directions[index as usize]);
+            let normal = (b - a).cross(c - a);
+            assert!(normal.dot(a + b + c) > 0.0, "triangle faces inward");
+            area += normal.length() / 2.0;
+        }
+        // Sum of the spherical triangles between the pole and each edge
+        let corners = ring_directions(&polygon.exterior);
+        let expected: f32 = (0..corners.len())
+            .map(|i| {
+                let (a, b, c) = (Vec3::Y, corners[i], corners[(i + 1) % corners.len()]);
+                let numerator = a.dot(b.cross(c)).abs();
+                2.0 * numerator.atan2(1.0 + a.dot(b) + b.dot(c) + c.dot(a))
+            })
+            .sum();
+        assert!((area - expected).abs() / expected < 0.01);
+    }
+}
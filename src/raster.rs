@@ -0,0 +1,288 @@
+use gdal::errors::GdalError;
+use gdal::raster::ResampleAlg;
+use gdal::spatial_ref::{CoordTransform, SpatialRef};
+use gdal::Dataset;
+
+use crate::map::HeightSource;
+
+pub struct RasterData {
+    pub dataset: Dataset,
+    pub transform: CoordTransform,
+}
+
+impl RasterData {
+    pub fn new(path: &str) -> Result<Self, GdalError> {
+        let dataset = Dataset::open(path)?;
+        let srs = dataset.spatial_ref()?;
+        let target_srs = SpatialRef::from_epsg(4326)?;
+        let transform = gdal::spatial_ref::CoordTransform::new(&srs, &target_srs)?;
+        Ok(Self { dataset, transform })
+    }
+    pub fn get_coordinate_height(
+        &self,
+        latitude: f64,
+        longitude: f64,
+    ) -> Result<Option<f64>, GdalError> {
+        let (lat, lon) = (latitude, longitude);
+        self.transform
+            .transform_coords(&mut [lon], &mut [lat], &mut [])?;
+        let raster_band = self.dataset.rasterband(1)?;
+        let transform = self.dataset.geo_transform().unwrap();
+        let x = (lon - transform[0]) / transform[1];
+        let y = (lat - transform[3]) / transform[5];
+        let mut res_buffer = raster_band.read_as::<f64>(
+            (x as isize, y as isize),
+            (1, 1),
+            (1, 1),
+            Some(ResampleAlg::Average),
+        )?;
+        Ok(res_buffer.data.pop())
+    }
+
+    /// Reads band 1 into memory, averaged down so it is at most `max_width` pixels wide.
+    ///
+    /// Like [`RasterData::get_coordinate_height`], the grid is addressed by longitude and
+    /// latitude, so it assumes a geographic raster.
+    pub fn read_grid(&self, max_width: usize) -> Result<RasterGrid, GdalError> {
+        self.read_grid_resampled(max_width, ResampleAlg::Average)
+    }
+
+    /// Like [`RasterData::read_grid`], but downsampled with `resample_alg`. Categorical data
+    /// such as land cover needs `ResampleAlg::NearestNeighbour`, so classes aren't averaged.
+    pub fn read_grid_resampled(
+        &self,
+        max_width: usize,
+        resample_alg: ResampleAlg,
+    ) -> Result<RasterGrid, GdalError> {
+        let (width, height) = self.dataset.raster_size();
+        let scale = (width as f64 / max_width.max(1) as f64).max(1.0);
+        let grid_width = ((width as f64 / scale).round() as usize).max(1);
+        let grid_height = ((height as f64 / scale).round() as usize).max(1);
+
+        let raster_band = self.dataset.rasterband(1)?;
+        let buffer = raster_band.read_as::<f64>(
+            (0, 0),
+            (width, height),
+            (grid_width, grid_height),
+            Some(resample_alg),
+        )?;
+
+        let mut geo_transform = self.dataset.geo_transform()?;
+        geo_transform[1] *= width as f64 / grid_width as f64;
+        geo_transform[5] *= height as f64 / grid_height as f64;
+        Ok(RasterGrid {
+            width: grid_width,
+            height: grid_height,
+            data: buffer.data,
+            geo_transform,
+            no_data: raster_band.no_data_value(),
+        })
+    }
+}
+
+/// A raster band held in memory, for sampling far more points than per-pixel reads allow
+pub struct RasterGrid {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f64>,
+    pub geo_transform: [f64; 6],
+    pub no_data: Option<f64>,
+}
+
+impl RasterGrid {
+    fn get(&self, x: usize, y: usize) -> Option<f64> {
+        let value = self.data[x + y * self.width];
+        (Some(value) != self.no_data && !value.is_nan()).then_some(value)
+    }
+
+    /// Bilinearly interpolated value at a latitude/longitude, or `None` outside the raster
+    pub fn sample(&self, latitude: f64, longitude: f64) -> Option<f64> {
+        // Pixel centres sit half a pixel in from the geotransform origin
+        let x = (longitude - self.geo_transform[0]) / self.geo_transform[1] - 0.5;
+        let y = (latitude - self.geo_transform[3]) / self.geo_transform[5] - 0.5;
+        let max_x = (self.width - 1) as f64;
+        let max_y = (self.height - 1) as f64;
+        if !(-0.5..=max_x + 0.5).contains(&x) || !(-0.5..=max_y + 0.5).contains(&y) {
+            return None;
+        }
+        let (x, y) = (x.clamp(0.0, max_x), y.clamp(0.0, max_y));
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+
+        let corners = [
+            (x0, y0, (1.0 - tx) * (1.0 - ty)),
+            (x1, y0, tx * (1.0 - ty)),
+            (x0, y1, (1.0 - tx) * ty),
+            (x1, y1, tx * ty),
+        ];
+        let mut value = 0.0;
+        for (x, y, weight) in corners {
+            if weight > 0.0 {
+                value += self.get(x, y)? * weight;
+            }
+        }
+        Some(value)
+    }
+
+    /// Value of the pixel containing a latitude/longitude, or `None` outside the raster
+    pub fn sample_nearest(&self, latitude: f64, longitude: f64) -> Option<f64> {
+        let x = ((longitude - self.geo_transform[0]) / self.geo_transform[1]).floor();
+        let y = ((latitude - self.geo_transform[3]) / self.geo_transform[5]).floor();
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return None;
+        }
+        self.get(x as usize, y as usize)
+    }
+}
+
+impl HeightSource for RasterGrid {
+    fn height_at(&self, latitude: f64, longitude: f64) -> Option<f64> {
+        self.sample(latitude, longitude)
+    }
+}
+
+/// Summary of a raster's georeferencing and bands
+pub struct RasterInfo {
+    pub driver: String,
+    pub size: (usize, usize),
+    /// `(min_x, min_y, max_x, max_y)` in the raster's CRS
+    pub bounds: (f64, f64, f64, f64),
+    /// Pixel size in CRS units; `y` is negative for north-up rasters
+    pub resolution: (f64, f64),
+    pub crs: String,
+    pub bands: Vec<BandInfo>,
+}
+
+pub struct BandInfo {
+    pub index: isize,
+    pub data_type: String,
+    pub no_data: Option<f64>,
+}
+
+/// Value statistics for one band, ignoring NoData and NaN pixels
+pub struct BandStatistics {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub valid_count: u64,
+    pub no_data_count: u64,
+    /// Pixel counts in equal-width bins from `min` to `max`
+    pub histogram: Vec<u64>,
+}
+
+impl RasterData {
+    pub fn info(&self) -> Result<RasterInfo, GdalError> {
+        let (width, height) = self.dataset.raster_size();
+        let gt = self.dataset.geo_transform()?;
+        let (x0, x1) = (gt[0], gt[0] + gt[1] * width as f64);
+        let (y0, y1) = (gt[3], gt[3] + gt[5] * height as f64);
+
+        let srs = self.dataset.spatial_ref()?;
+        let crs = match (srs.auth_name(), srs.auth_code()) {
+            (Ok(name), Ok(code)) => format!("{} ({name}:{code})", srs.name()?),
+            _ => srs.name()?,
+        };
+
+        let mut bands = Vec::new();
+        for index in 1..=self.dataset.raster_count() {
+            let band = self.dataset.rasterband(index)?;
+            bands.push(BandInfo {
+                index,
+                data_type: band.band_type().name(),
+                no_data: band.no_data_value(),
+            });
+        }
+
+        Ok(RasterInfo {
+            driver: self.dataset.driver().long_name(),
+            size: (width, height),
+            bounds: (x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)),
+            resolution: (gt[1], gt[5]),
+            crs,
+            bands,
+        })
+    }
+
+    /// Scans a band row by row to compute its statistics and a histogram with `bins` bins
+    pub fn band_statistics(&self, index: isize, bins: usize) -> Result<BandStatistics, GdalError> {
+        let band = self.dataset.rasterband(index)?;
+        let no_data = band.no_data_value();
+        let (width, height) = self.dataset.raster_size();
+        let read_row = |row: usize| {
+            band.read_as::<f64>((0, row as isize), (width, 1), (width, 1), None)
+                .map(|buffer| buffer.data)
+        };
+        let is_valid = |value: &f64| Some(*value) != no_data && !value.is_nan();
+
+        let (mut min, mut max, mut sum) = (f64::INFINITY, f64::NEG_INFINITY, 0.0);
+        let (mut valid_count, mut no_data_count) = (0, 0);
+        for row in 0..height {
+            for value in read_row(row)? {
+                if is_valid(&value) {
+                    min = min.min(value);
+                    max = max.max(value);
+                    sum += value;
+                    valid_count += 1;
+                } else {
+                    no_data_count += 1;
+                }
+            }
+        }
+
+        let mut histogram = vec![0; bins.max(1)];
+        if valid_count > 0 {
+            let bin_width = (max - min) / histogram.len() as f64;
+            for row in 0..height {
+                for value in read_row(row)?.into_iter().filter(is_valid) {
+                    let bin = if bin_width > 0.0 {
+                        ((value - min) / bin_width) as usize
+                    } else {
+                        0
+                    };
+                    histogram[bin.min(bins.max(1) - 1)] += 1;
+                }
+            }
+        }
+
+        Ok(BandStatistics {
+            min,
+            max,
+            mean: sum / valid_count.max(1) as f64,
+            valid_count,
+            no_data_count,
+            histogram,
+        })
+    }
+}
+
+impl HeightSource for RasterData {
+    fn height_at(&self, latitude: f64, longitude: f64) -> Option<f64> {
+        self.get_coordinate_height(latitude, longitude)
+            .ok()
+            .flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raster_map() {
+        let raster_data =
+            RasterData::new("assets/Bathymetry/gebco_2023_n47.7905_s39.9243_w25.6311_e42.9895.tif")
+                .unwrap();
+
+        // Mt Elbrus
+        let tgt_latitude = 43.351851;
+        let tgt_longitude = 42.4368771;
+
+        let elevation = raster_data
+            .get_coordinate_height(tgt_latitude, tgt_longitude)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(elevation, 5392.0);
+    }
+}
@@ -0,0 +1,212 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::overlay::Overlay;
+use crate::scale::{ColorScale, Palette};
+
+/// How the values of a data raster are turned into colors
+#[derive(Clone, Debug)]
+pub enum RasterColoring {
+    /// Continuous data such as temperature. A scale without a domain is fitted to the band's
+    /// range.
+    Colormap(ColorScale),
+    /// Class codes such as land cover, each with its own color. Codes missing from the table
+    /// are transparent.
+    Categories(HashMap<i64, Color>),
+}
+
+impl RasterColoring {
+    pub fn categories(classes: impl IntoIterator<Item = (i64, Color)>) -> Self {
+        Self::Categories(classes.into_iter().collect())
+    }
+
+    /// Whether the values are class codes, which mustn't be averaged or interpolated
+    pub fn is_categorical(&self) -> bool {
+        matches!(self, RasterColoring::Categories(_))
+    }
+
+    /// This coloring with its colormap fitted to `values`, unless it already has a domain
+    pub fn fit(&self, values: impl IntoIterator<Item = f64>) -> Self {
+        match self {
+            RasterColoring::Colormap(scale) => {
+                RasterColoring::Colormap(scale.fit(values.into_iter().map(|value| value as f32)))
+            }
+            RasterColoring::Categories(_) => self.clone(),
+        }
+    }
+
+    /// Color of a value, or `None` for unknown categories
+    pub fn color(&self, value: f64) -> Option<Color> {
+        match self {
+            RasterColoring::Colormap(scale) => Some(scale.color(value as f32)),
+            RasterColoring::Categories(classes) => classes.get(&(value.round() as i64)).copied(),
+        }
+    }
+}
+
+/// Drapes a single-band GeoTIFF, or any other raster GDAL reads, over the terrain as a colored
+/// overlay.
+///
+/// Like elevation rasters, the data must be in geographic coordinates. NoData pixels and areas
+/// outside the raster are left transparent. The raster is read once, and again if the layer
+/// changes. It is shown through an [`Overlay`] on the same entity, whose opacity can be
+/// changed afterwards.
+#[derive(Component)]
+pub struct RasterLayer {
+    pub path: String,
+    pub coloring: RasterColoring,
+    pub opacity: f32,
+    /// Width of the overlay image in pixels. It is half as tall.
+    pub resolution: usize,
+}
+
+impl RasterLayer {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            coloring: RasterColoring::Colormap(ColorScale::new(Palette::Viridis)),
+            opacity: 0.6,
+            resolution: 2048,
+        }
+    }
+
+    pub fn with_colormap(mut self, scale: ColorScale) -> Self {
+        self.coloring = RasterColoring::Colormap(scale);
+        self
+    }
+
+    pub fn with_categories(mut self, classes: impl IntoIterator<Item = (i64, Color)>) -> Self {
+        self.coloring = RasterColoring::categories(classes);
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
+    pub fn with_resolution(mut self, width: usize) -> Self {
+        self.resolution = width;
+        self
+    }
+}
+
+/// Added to a [`RasterLayer`] entity once its raster has been read
+#[derive(Component)]
+pub struct RasterLayerLoaded;
+
+#[cfg(feature = "elevation-gdal")]
+fn load_raster_image(layer: &RasterLayer) -> Option<Image> {
+    use gdal::raster::ResampleAlg;
+
+    use crate::overlay::equirectangular_image;
+    use crate::raster::RasterData;
+
+    let width = layer.resolution.max(2);
+    let categorical = layer.coloring.is_categorical();
+    let resample_alg = if categorical {
+        ResampleAlg::NearestNeighbour
+    } else {
+        ResampleAlg::Average
+    };
+    let grid = RasterData::new(&layer.path)
+        .and_then(|raster| raster.read_grid_resampled(width, resample_alg));
+    let grid = match grid {
+        Ok(grid) => grid,
+        Err(err) => {
+            error!("Failed to read raster layer {}: {err}", layer.path);
+            return None;
+        }
+    };
+    let coloring = layer.coloring.fit(
+        grid.data
+            .iter()
+            .copied()
+            .filter(|value| Some(*value) != grid.no_data && value.is_finite()),
+    );
+    Some(equirectangular_image(
+        width,
+        width / 2,
+        |latitude, longitude| {
+            let value = if categorical {
+                grid.sample_nearest(latitude, longitude)
+            } else {
+                grid.sample(latitude, longitude)
+            };
+            value
+                .and_then(|value| coloring.color(value))
+                .unwrap_or(Color::NONE)
+        },
+    ))
+}
+
+#[cfg(not(feature = "elevation-gdal"))]
+fn load_raster_image(layer: &RasterLayer) -> Option<Image> {
+    error!(
+        "Reading raster layer {} requires the `elevation-gdal` feature",
+        layer.path
+    );
+    None
+}
+
+/// System to read and colorize raster layers when they are spawned or changed
+pub fn update_raster_layers(
+    mut commands: Commands,
+    mut layers: Query<(Entity, Ref<RasterLayer>, Option<&mut Overlay>)>,
+    loaded: Query<(), With<RasterLayerLoaded>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for (entity, layer, overlay) in layers.iter_mut() {
+        if loaded.contains(entity) && !layer.is_changed() {
+            continue;
+        }
+        commands.entity(entity).insert(RasterLayerLoaded);
+        let Some(image) = load_raster_image(&layer) else {
+            continue;
+        };
+        match overlay {
+            Some(mut overlay) => {
+                overlay.image = images.add(image);
+                overlay.opacity = layer.opacity;
+            }
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(Overlay::new(images.add(image)).with_opacity(layer.opacity));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overlay::equirectangular_image;
+
+    #[test]
+    fn test_raster_coloring() {
+        let colormap =
+            RasterColoring::Colormap(ColorScale::from_colors(vec![Color::BLACK, Color::WHITE]))
+                .fit([-10.0, 30.0, 10.0]);
+        assert_eq!(colormap.color(10.0), Some(Color::rgba(0.5, 0.5, 0.5, 1.0)));
+        assert_eq!(colormap.color(50.0), Some(Color::WHITE));
+
+        let land_cover = RasterColoring::categories([(10, Color::DARK_GREEN), (80, Color::BLUE)]);
+        assert!(land_cover.is_categorical());
+        assert_eq!(land_cover.color(80.0), Some(Color::BLUE));
+        assert_eq!(land_cover.color(10.2), Some(Color::DARK_GREEN));
+        assert_eq!(land_cover.color(50.0), None);
+
+        // Pixels are colored at their centers, from the north-west corner
+        let image = equirectangular_image(4, 2, |latitude, longitude| {
+            if latitude > 0.0 && longitude < -90.0 {
+                Color::WHITE
+            } else {
+                Color::NONE
+            }
+        });
+        assert_eq!(image.texture_descriptor.size.width, 4);
+        assert_eq!(&image.data[..8], &[255, 255, 255, 255, 0, 0, 0, 0]);
+        assert!(image.data[16..].iter().all(|channel| *channel == 0));
+    }
+}
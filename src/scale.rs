@@ -0,0 +1,301 @@
+use bevy::prelude::*;
+
+/// How values are spaced along a scale before being mapped to colors or sizes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScaleType {
+    #[default]
+    Linear,
+    /// Natural logarithm, for values spanning orders of magnitude. Values below the smallest
+    /// positive float are clamped to it, and fitting a domain ignores them.
+    Log,
+    /// Square root, so that areas rather than radii grow linearly with the value
+    Sqrt,
+}
+
+impl ScaleType {
+    fn transform(self, value: f32) -> f32 {
+        match self {
+            ScaleType::Linear => value,
+            ScaleType::Log => value.max(f32::MIN_POSITIVE).ln(),
+            ScaleType::Sqrt => value.signum() * value.abs().sqrt(),
+        }
+    }
+
+    /// Whether a value has a meaningful position on this scale
+    fn accepts(self, value: f32) -> bool {
+        value.is_finite() && (self != ScaleType::Log || value > 0.0)
+    }
+}
+
+/// Built-in color palettes.
+///
+/// The viridis family is perceptually uniform and readable with color vision deficiencies.
+/// `BlueRed` is a color-blind-safe diverging palette and `OkabeIto` a color-blind-safe
+/// categorical one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Palette {
+    Viridis,
+    Magma,
+    Cividis,
+    BlueRed,
+    OkabeIto,
+    /// Pale yellow to orange-red, the original population marker ramp
+    YellowRed,
+}
+
+impl Palette {
+    pub fn colors(self) -> Vec<Color> {
+        let hex: &[&str] = match self {
+            Palette::Viridis => &[
+                "440154", "482878", "3e4989", "31688e", "26828e", "1f9e89", "35b779", "6ece58",
+                "b5de2b", "fde725",
+            ],
+            Palette::Magma => &[
+                "000004", "180f3d", "440f76", "721f81", "9e2f7f", "cd4071", "f1605d", "fd9668",
+                "feca8d", "fcfdbf",
+            ],
+            Palette::Cividis => &[
+                "00224e", "123570", "3b496c", "575d6d", "707173", "8a8779", "a69d75", "c4b56c",
+                "e4cf5b", "fee838",
+            ],
+            Palette::BlueRed => &[
+                "2166ac", "4393c3", "92c5de", "d1e5f0", "f7f7f7", "fddbc7", "f4a582", "d6604d",
+                "b2182b",
+            ],
+            Palette::OkabeIto => &[
+                "e69f00", "56b4e9", "009e73", "f0e442", "0072b2", "d55e00", "cc79a7", "000000",
+            ],
+            Palette::YellowRed => &["ffff80", "ff4d1a"],
+        };
+        hex.iter().map(|hex| Color::hex(hex).unwrap()).collect()
+    }
+}
+
+fn lerp_color(a: Color, b: Color, t: f32) -> Color {
+    let [ar, ag, ab, aa] = a.as_rgba_f32();
+    let [br, bg, bb, ba] = b.as_rgba_f32();
+    // Weighted rather than `a + (b - a) * t`, so both ends come out exactly
+    let mix = |a: f32, b: f32| a * (1.0 - t) + b * t;
+    Color::rgba(mix(ar, br), mix(ag, bg), mix(ab, bb), mix(aa, ba))
+}
+
+/// Normalizes `value` to 0..=1 within `domain`, splitting at `midpoint` if there is one so that
+/// it lands on 0.5
+fn normalize(value: f32, domain: (f32, f32), midpoint: Option<f32>, scale_type: ScaleType) -> f32 {
+    let t = |value: f32, from: f32, to: f32| {
+        let (value, from, to) = (
+            scale_type.transform(value),
+            scale_type.transform(from),
+            scale_type.transform(to),
+        );
+        if to > from {
+            ((value - from) / (to - from)).clamp(0.0, 1.0)
+        } else {
+            0.5
+        }
+    };
+    let (min, max) = domain;
+    match midpoint {
+        Some(midpoint) if value < midpoint => t(value, min.min(midpoint), midpoint) * 0.5,
+        Some(midpoint) => 0.5 + t(value, midpoint, max.max(midpoint)) * 0.5,
+        None => t(value, min, max),
+    }
+}
+
+/// Smallest and largest value the scale type accepts, or `None` if there are none
+fn extent(values: impl IntoIterator<Item = f32>, scale_type: ScaleType) -> Option<(f32, f32)> {
+    values
+        .into_iter()
+        .filter(|value| scale_type.accepts(*value))
+        .fold(None, |extent, value| match extent {
+            Some((min, max)) => Some((value.min(min), value.max(max))),
+            None => Some((value, value)),
+        })
+}
+
+/// Maps data values to colors by interpolating evenly spaced color stops.
+///
+/// Values outside the domain get the color at the nearer end.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorScale {
+    pub colors: Vec<Color>,
+    /// Values mapped to the first and last color, or `None` to fit the data's range
+    pub domain: Option<(f32, f32)>,
+    /// Value mapped to the middle color of a diverging scale
+    pub midpoint: Option<f32>,
+    pub scale_type: ScaleType,
+}
+
+impl Default for ColorScale {
+    fn default() -> Self {
+        Self::new(Palette::Viridis)
+    }
+}
+
+impl ColorScale {
+    pub fn new(palette: Palette) -> Self {
+        Self::from_colors(palette.colors())
+    }
+
+    pub fn from_colors(colors: Vec<Color>) -> Self {
+        Self {
+            colors,
+            domain: None,
+            midpoint: None,
+            scale_type: ScaleType::Linear,
+        }
+    }
+
+    /// A diverging scale with its middle color at `midpoint`
+    pub fn diverging(palette: Palette, midpoint: f32) -> Self {
+        Self::new(palette).with_midpoint(midpoint)
+    }
+
+    pub fn with_domain(mut self, min: f32, max: f32) -> Self {
+        self.domain = Some((min, max));
+        self
+    }
+
+    pub fn with_midpoint(mut self, midpoint: f32) -> Self {
+        self.midpoint = Some(midpoint);
+        self
+    }
+
+    pub fn with_scale_type(mut self, scale_type: ScaleType) -> Self {
+        self.scale_type = scale_type;
+        self
+    }
+
+    /// This scale with its domain set to the range of `values`, unless it already has one
+    pub fn fit(&self, values: impl IntoIterator<Item = f32>) -> Self {
+        Self {
+            domain: self.domain.or_else(|| extent(values, self.scale_type)),
+            ..self.clone()
+        }
+    }
+
+    /// Color at `t` from 0 to 1 along the stops
+    pub fn sample(&self, t: f32) -> Color {
+        match self.colors.len() {
+            0 => Color::WHITE,
+            1 => self.colors[0],
+            len => {
+                let position = t.clamp(0.0, 1.0) * (len - 1) as f32;
+                let index = (position.floor() as usize).min(len - 2);
+                lerp_color(
+                    self.colors[index],
+                    self.colors[index + 1],
+                    position - index as f32,
+                )
+            }
+        }
+    }
+
+    pub fn color(&self, value: f32) -> Color {
+        let domain = self.domain.unwrap_or((0.0, 1.0));
+        self.sample(normalize(value, domain, self.midpoint, self.scale_type))
+    }
+
+    /// Color of the `index`th category, cycling through the stops without interpolating
+    pub fn categorical(&self, index: usize) -> Color {
+        if self.colors.is_empty() {
+            Color::WHITE
+        } else {
+            self.colors[index % self.colors.len()]
+        }
+    }
+}
+
+/// Maps data values to marker sizes
+#[derive(Clone, Debug, PartialEq)]
+pub struct SizeScale {
+    /// Sizes of the smallest and largest values
+    pub range: (f32, f32),
+    /// Values mapped to the ends of `range`, or `None` to fit the data's range
+    pub domain: Option<(f32, f32)>,
+    pub scale_type: ScaleType,
+}
+
+impl SizeScale {
+    pub fn new(min: f32, max: f32) -> Self {
+        Self {
+            range: (min, max),
+            domain: None,
+            scale_type: ScaleType::Linear,
+        }
+    }
+
+    pub fn with_domain(mut self, min: f32, max: f32) -> Self {
+        self.domain = Some((min, max));
+        self
+    }
+
+    pub fn with_scale_type(mut self, scale_type: ScaleType) -> Self {
+        self.scale_type = scale_type;
+        self
+    }
+
+    /// This scale with its domain set to the range of `values`, unless it already has one
+    pub fn fit(&self, values: impl IntoIterator<Item = f32>) -> Self {
+        Self {
+            domain: self.domain.or_else(|| extent(values, self.scale_type)),
+            ..self.clone()
+        }
+    }
+
+    /// Size of a value, clamped to the range
+    pub fn size(&self, value: f32) -> f32 {
+        let domain = self.domain.unwrap_or((0.0, 1.0));
+        let t = normalize(value, domain, None, self.scale_type);
+        self.range.0 + (self.range.1 - self.range.0) * t
+    }
+
+    pub fn max_size(&self) -> f32 {
+        self.range.0.max(self.range.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_scale() {
+        let scale = ColorScale::from_colors(vec![Color::BLACK, Color::WHITE]).fit([2.0, 6.0, 4.0]);
+        assert_eq!(scale.domain, Some((2.0, 6.0)));
+        assert_eq!(scale.color(4.0), Color::rgba(0.5, 0.5, 0.5, 1.0));
+        assert_eq!(scale.color(-10.0), Color::BLACK);
+        assert_eq!(scale.color(10.0), Color::WHITE);
+
+        let log = ColorScale::from_colors(vec![Color::BLACK, Color::WHITE])
+            .with_domain(1.0, 100.0)
+            .with_scale_type(ScaleType::Log);
+        assert!((log.color(10.0).r() - 0.5).abs() < 1e-5);
+        // Zero has no logarithm, so it is left out of a fitted domain
+        let fitted = ColorScale::from_colors(vec![Color::BLACK, Color::WHITE])
+            .with_scale_type(ScaleType::Log)
+            .fit([0.0, 1.0, 100.0]);
+        assert_eq!(fitted.domain, Some((1.0, 100.0)));
+        assert!((fitted.color(10.0).r() - 0.5).abs() < 1e-5);
+
+        let diverging = ColorScale::new(Palette::BlueRed)
+            .with_domain(-1.0, 10.0)
+            .with_midpoint(0.0);
+        assert_eq!(diverging.color(0.0), Palette::BlueRed.colors()[4]);
+        assert_eq!(diverging.color(-1.0), Palette::BlueRed.colors()[0]);
+        assert_eq!(diverging.color(10.0), Palette::BlueRed.colors()[8]);
+    }
+
+    #[test]
+    fn test_size_scale() {
+        let scale = SizeScale::new(2.0, 7.0).with_domain(5.0, 40.0);
+        assert_eq!(scale.size(5.0), 2.0);
+        assert_eq!(scale.size(40.0), 7.0);
+        assert_eq!(scale.size(100.0), 7.0);
+
+        let sqrt = SizeScale::new(0.0, 10.0)
+            .with_scale_type(ScaleType::Sqrt)
+            .fit([0.0, 100.0]);
+        assert_eq!(sqrt.size(25.0), 5.0);
+    }
+}
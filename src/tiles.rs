@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+
+use crate::map::{Coordinates, HeightSource};
+
+const MAGIC: &[u8; 4] = b"BETL";
+const FORMAT_VERSION: u32 = 1;
+/// File in a tile set's root directory describing the tiles
+pub(crate) const MANIFEST: &str = "tiles.txt";
+
+/// Stored in place of a height where the source raster had no data
+pub const NO_DATA: i16 = i16::MIN;
+
+/// Largest number of samples along a tile's edge, which keeps a tile below 64 MB
+pub const MAX_TILE_SIZE: u32 = 4097;
+
+/// Deepest tile level, which keeps tile coordinates and sample counts within `u32`
+pub const MAX_LEVEL: u8 = 15;
+
+/// The six cube faces, in the same order `generate_faces` builds them
+pub const CUBE_FACES: [Vec3; 6] = [
+    Vec3::X,
+    Vec3::NEG_X,
+    Vec3::Y,
+    Vec3::NEG_Y,
+    Vec3::Z,
+    Vec3::NEG_Z,
+];
+
+/// The horizontal and vertical axes spanning a cube face, matching `generate_face`
+pub fn face_axes(normal: Vec3) -> (Vec3, Vec3) {
+    let axis_a = Vec3::new(normal.y, normal.z, normal.x);
+    let axis_b = axis_a.cross(normal);
+    (axis_a, axis_b)
+}
+
+/// Address of a tile in the per-face quadtree.
+///
+/// Level 0 covers a whole cube face and each level splits its parent into four.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileId {
+    pub face: u8,
+    pub level: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileId {
+    pub fn new(face: u8, level: u8, x: u32, y: u32) -> Self {
+        Self { face, level, x, y }
+    }
+
+    /// Number of tiles along each edge of a face at this tile's level
+    pub fn tiles_per_edge(&self) -> u32 {
+        1 << self.level
+    }
+
+    pub fn children(&self) -> [TileId; 4] {
+        let level = self.level + 1;
+        let (x, y) = (self.x * 2, self.y * 2);
+        [
+            TileId::new(self.face, level, x, y),
+            TileId::new(self.face, level, x + 1, y),
+            TileId::new(self.face, level, x, y + 1),
+            TileId::new(self.face, level, x + 1, y + 1),
+        ]
+    }
+
+    /// Location of this tile's file below a tile set's root directory
+    pub fn path(&self, root: impl AsRef<Path>) -> PathBuf {
+        root.as_ref()
+            .join(self.face.to_string())
+            .join(self.level.to_string())
+            .join(format!("{}_{}.tile", self.x, self.y))
+    }
+
+    /// Unit-sphere direction of the point at `(u, v)`, each in `0..=1`, within this tile
+    pub fn direction(&self, u: f32, v: f32) -> Vec3 {
+        let normal = CUBE_FACES[self.face as usize];
+        let (axis_a, axis_b) = face_axes(normal);
+        let n = self.tiles_per_edge() as f32;
+        let s = (self.x as f32 + u) / n * 2.0 - 1.0;
+        let t = (self.y as f32 + v) / n * 2.0 - 1.0;
+        (normal + s * axis_a + t * axis_b).normalize()
+    }
+
+    /// Finds the tile at `level` containing `direction`, and the `(u, v)` position within it
+    pub fn locate(direction: Vec3, level: u8) -> (TileId, f32, f32) {
+        let abs = direction.abs();
+        let face = if abs.x >= abs.y && abs.x >= abs.z {
+            if direction.x > 0.0 {
+                0
+            } else {
+                1
+            }
+        } else if abs.y >= abs.z {
+            if direction.y > 0.0 {
+                2
+            } else {
+                3
+            }
+        } else if direction.z > 0.0 {
+            4
+        } else {
+            5
+        };
+        let normal = CUBE_FACES[face as usize];
+        let (axis_a, axis_b) = face_axes(normal);
+        let on_cube = direction / direction.dot(normal);
+
+        let n = (1u32 << level) as f32;
+        let fx = ((on_cube.dot(axis_a) + 1.0) * 0.5 * n).clamp(0.0, n);
+        let fy = ((on_cube.dot(axis_b) + 1.0) * 0.5 * n).clamp(0.0, n);
+        let x = (fx.floor() as u32).min((n as u32) - 1);
+        let y = (fy.floor() as u32).min((n as u32) - 1);
+        (TileId::new(face, level, x, y), fx - x as f32, fy - y as f32)
+    }
+}
+
+/// A square grid of heights in metres, sampled at the tile's corners and edges inclusive so
+/// neighbouring tiles share their border samples
+pub struct HeightTile {
+    pub id: TileId,
+    pub size: u32,
+    pub heights: Vec<i16>,
+}
+
+impl HeightTile {
+    /// Builds a tile by sampling `source` at every grid point
+    pub fn generate(id: TileId, size: u32, source: &(impl HeightSource + ?Sized)) -> Self {
+        let mut heights = Vec::with_capacity(size as usize * size as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let u = x as f32 / (size - 1) as f32;
+                let v = y as f32 / (size - 1) as f32;
+                let (lat, lon) = Coordinates::from(id.direction(u, v)).as_degrees();
+                let height = source
+                    .height_at(lat as f64, lon as f64)
+                    .map(|h| h.round().clamp(NO_DATA as f64 + 1.0, i16::MAX as f64) as i16)
+                    .unwrap_or(NO_DATA);
+                heights.push(height);
+            }
+        }
+        Self { id, size, heights }
+    }
+
+    fn get(&self, x: u32, y: u32) -> Option<f32> {
+        let height = self.heights[(x + y * self.size) as usize];
+        (height != NO_DATA).then_some(height as f32)
+    }
+
+    /// Bilinearly interpolated height at `(u, v)`, each in `0..=1`
+    pub fn sample(&self, u: f32, v: f32) -> Option<f32> {
+        let last = (self.size - 1) as f32;
+        let fx = (u * last).clamp(0.0, last);
+        let fy = (v * last).clamp(0.0, last);
+        let (x0, y0) = (fx.floor() as u32, fy.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+        let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+
+        let corners = [
+            (x0, y0, (1.0 - tx) * (1.0 - ty)),
+            (x1, y0, tx * (1.0 - ty)),
+            (x0, y1, (1.0 - tx) * ty),
+            (x1, y1, tx * ty),
+        ];
+        let mut height = 0.0;
+        for (x, y, weight) in corners {
+            // Missing samples only matter if they contribute to the result
+            if weight > 0.0 {
+                height += self.get(x, y)? * weight;
+            }
+        }
+        Some(height)
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&[self.id.face, self.id.level])?;
+        writer.write_all(&self.id.x.to_le_bytes())?;
+        writer.write_all(&self.id.y.to_le_bytes())?;
+        writer.write_all(&self.size.to_le_bytes())?;
+        for height in &self.heights {
+            writer.write_all(&height.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read(mut reader: impl Read) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a height tile"));
+        }
+        if read_u32(&mut reader)? != FORMAT_VERSION {
+            return Err(invalid_data("unsupported height tile version"));
+        }
+        let mut face_level = [0; 2];
+        reader.read_exact(&mut face_level)?;
+        let x = read_u32(&mut reader)?;
+        let y = read_u32(&mut reader)?;
+        let size = read_u32(&mut reader)?;
+        if size < 2 {
+            return Err(invalid_data("height tile is smaller than 2x2"));
+        }
+        if size > MAX_TILE_SIZE {
+            return Err(invalid_data("height tile is too large"));
+        }
+
+        let mut bytes = vec![0; size as usize * size as usize * 2];
+        reader.read_exact(&mut bytes)?;
+        let heights = bytes
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        Ok(Self {
+            id: TileId::new(face_level[0], face_level[1], x, y),
+            size,
+            heights,
+        })
+    }
+
+    pub fn save(&self, root: impl AsRef<Path>) -> io::Result<()> {
+        let path = self.id.path(root);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(root: impl AsRef<Path>, id: TileId) -> io::Result<Self> {
+        let tile = Self::read(BufReader::new(File::open(id.path(root))?))?;
+        if tile.id != id {
+            return Err(invalid_data("height tile is stored under the wrong path"));
+        }
+        Ok(tile)
+    }
+}
+
+/// A directory of height tiles written by `earth-tiler`, usable as the terrain's height source.
+///
+/// Heights are sampled from the deepest level by default, or from the coarsest level detailed
+/// enough for the terrain with [`TileSet::with_detail`], so coarse terrain reads fewer tiles.
+/// Tiles are read lazily and kept in memory once loaded, and where a tile is missing its
+/// parents are sampled instead.
+pub struct TileSet {
+    root: PathBuf,
+    pub tile_size: u32,
+    pub max_level: u8,
+    /// Level heights are sampled from
+    pub level: u8,
+    tiles: Mutex<HashMap<TileId, Option<HeightTile>>>,
+}
+
+impl TileSet {
+    pub fn open(root: impl AsRef<Path>) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        let (tile_size, max_level) =
+            Self::parse_manifest(&fs::read_to_string(root.join(MANIFEST))?)?;
+        Ok(Self {
+            root,
+            tile_size,
+            max_level,
+            level: max_level,
+            tiles: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Tile size and deepest level listed in a manifest
+    fn parse_manifest(manifest: &str) -> io::Result<(u32, u8)> {
+        let mut tile_size = None;
+        let mut max_level = None;
+        for line in manifest.lines() {
+            match line.split_once(' ') {
+                Some(("tile_size", value)) => tile_size = value.trim().parse().ok(),
+                Some(("max_level", value)) => max_level = value.trim().parse().ok(),
+                _ => {}
+            }
+        }
+        match (tile_size, max_level) {
+            (Some(tile_size), _) if !(2..=MAX_TILE_SIZE).contains(&tile_size) => {
+                Err(invalid_data("tile manifest has an invalid tile_size"))
+            }
+            (_, Some(max_level)) if max_level > MAX_LEVEL => {
+                Err(invalid_data("tile manifest has too deep a max_level"))
+            }
+            (Some(tile_size), Some(max_level)) => Ok((tile_size, max_level)),
+            _ => Err(invalid_data(
+                "tile manifest is missing tile_size or max_level",
+            )),
+        }
+    }
+
+    /// Samples the coarsest level with at least `samples_per_face_edge` samples along each cube
+    /// face, or the deepest level if none has that many
+    pub fn with_detail(mut self, samples_per_face_edge: u32) -> Self {
+        self.level = Self::level_for_detail(self.tile_size, self.max_level, samples_per_face_edge);
+        self
+    }
+
+    fn level_for_detail(tile_size: u32, max_level: u8, samples_per_face_edge: u32) -> u8 {
+        let spacings = tile_size.saturating_sub(1).max(1) as u64;
+        (0..max_level)
+            .find(|level| spacings << level >= samples_per_face_edge as u64)
+            .unwrap_or(max_level)
+    }
+
+    /// Height at `(u, v)` in the tile, or in its nearest loaded ancestor if it is missing
+    fn sample(&self, id: TileId, u: f32, v: f32) -> Option<f32> {
+        let mut tiles = self.tiles.lock().unwrap();
+        let (mut id, mut u, mut v) = (id, u, v);
+        loop {
+            let tile = tiles.entry(id).or_insert_with(|| {
+                HeightTile::load(&self.root, id)
+                    .map_err(|err| warn!("Failed to load height tile {id:?}: {err}"))
+                    .ok()
+            });
+            if let Some(tile) = tile {
+                return tile.sample(u, v);
+            }
+            if id.level == 0 {
+                return None;
+            }
+            // The same point within the parent tile
+            u = (u + (id.x % 2) as f32) / 2.0;
+            v = (v + (id.y % 2) as f32) / 2.0;
+            id = TileId::new(id.face, id.level - 1, id.x / 2, id.y / 2);
+        }
+    }
+
+    /// Writes the manifest describing a tile set to its root directory.
+    ///
+    /// The manifest records when it was written, so meshes cached from earlier tiles stop
+    /// matching once the tiles are regenerated.
+    pub fn write_manifest(root: impl AsRef<Path>, tile_size: u32, max_level: u8) -> io::Result<()> {
+        let generation = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        fs::create_dir_all(&root)?;
+        fs::write(
+            root.as_ref().join(MANIFEST),
+            format!("tile_size {tile_size}\nmax_level {max_level}\ngeneration {generation}\n"),
+        )
+    }
+}
+
+impl HeightSource for TileSet {
+    fn height_at(&self, latitude: f64, longitude: f64) -> Option<f64> {
+        let coords = Coordinates::from_degrees(latitude as f32, longitude as f32).ok()?;
+        let (id, u, v) = TileId::locate(coords.get_point_on_sphere(), self.level);
+        self.sample(id, u, v).map(|height| height as f64)
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate_inverts_direction() {
+        for face in 0..6 {
+            let id = TileId::new(face, 3, 5, 2);
+            let (located, u, v) = TileId::locate(id.direction(0.25, 0.75), 3);
+            assert_eq!(located, id);
+            assert!((u - 0.25).abs() < 1e-4);
+            assert!((v - 0.75).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_tile_round_trip_and_sampling() {
+        let tile = HeightTile {
+            id: TileId::new(2, 1, 1, 0),
+            size: 2,
+            heights: vec![0, 100, 200, NO_DATA],
+        };
+        let mut bytes = Vec::new();
+        tile.write(&mut bytes).unwrap();
+        let loaded = HeightTile::read(bytes.as_slice()).unwrap();
+
+        assert_eq!(loaded.id, tile.id);
+        assert_eq!(loaded.heights, tile.heights);
+        assert_eq!(loaded.sample(0.5, 0.0), Some(50.0));
+        assert_eq!(loaded.sample(0.0, 0.5), Some(100.0));
+        assert_eq!(loaded.sample(1.0, 1.0), None);
+
+        // Sizes too large to allocate are rejected
+        bytes[18..22].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(HeightTile::read(bytes.as_slice()).is_err());
+
+        // The coarsest level with enough samples is picked
+        assert_eq!(TileSet::level_for_detail(257, 3, 200), 0);
+        assert_eq!(TileSet::level_for_detail(257, 3, 600), 2);
+        assert_eq!(TileSet::level_for_detail(257, 3, 5000), 3);
+
+        // Manifests with levels too deep to address are rejected
+        assert_eq!(
+            TileSet::parse_manifest("tile_size 257\nmax_level 15\n").unwrap(),
+            (257, 15)
+        );
+        assert!(TileSet::parse_manifest("tile_size 257\nmax_level 32\n").is_err());
+        assert!(TileSet::parse_manifest("tile_size 0\nmax_level 3\n").is_err());
+    }
+}